@@ -0,0 +1,154 @@
+extern crate criterion;
+extern crate im_rc;
+extern crate mal_rust;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use im_rc::Vector;
+use mal_rust::env::Env;
+use mal_rust::eval::{apply, eval, repl_env, Engine, EnvBuilder};
+use mal_rust::reader::{read_all, read_str};
+use mal_rust::types::{MalType, Sym};
+
+/// A list of `width` vectors, each holding `width` maps of a string and a number.
+fn nested(width: i64) -> MalType {
+    let row = |i: i64| {
+        (0..width)
+            .map(|j| {
+                let mut map = im_rc::HashMap::new();
+                map.insert(MalType::from(format!("k{}", j)), MalType::Integer(i * j));
+                MalType::HashMap(map)
+            })
+            .collect::<Vector<MalType>>()
+    };
+    MalType::List((0..width).map(|i| MalType::Vector(row(i))).collect())
+}
+
+/// A program of `n` small function definitions and calls.
+fn program(n: usize) -> String {
+    (0..n)
+        .map(|i| {
+            format!(
+                "(def! f{0} (fn* (a b) (if (< a b) (+ a {0}) (let* (c (* a b)) [c \"{0}\" :k{0}]))))\n(f{0} 1 2)\n",
+                i
+            )
+        })
+        .collect()
+}
+
+fn reader(c: &mut Criterion) {
+    let data = nested(300).pr_str(true);
+    let source = program(2000);
+    let mut group = c.benchmark_group("reader");
+    group.sample_size(20);
+    group.bench_function(format!("read_str {} KB of data", data.len() / 1024), |b| {
+        b.iter(|| read_str(black_box(data.clone())).unwrap())
+    });
+    group.bench_function(format!("read_all {} KB of code", source.len() / 1024), |b| {
+        b.iter(|| read_all(black_box(source.clone())).unwrap())
+    });
+    group.finish();
+}
+
+fn printer(c: &mut Criterion) {
+    let value = nested(100);
+    c.bench_function("pr_str nested 100x100", |b| b.iter(|| black_box(&value).pr_str(true)));
+    c.bench_function("display nested 100x100", |b| {
+        b.iter(|| black_box(&value).to_string())
+    });
+}
+
+fn env(c: &mut Criterion) {
+    let global = repl_env();
+    let mut local = global.clone();
+    for depth in 0..10 {
+        local = local.new_env();
+        for slot in 0..3 {
+            local.set(format!("x{}-{}", depth, slot), MalType::Integer(slot));
+        }
+    }
+    let plus = Sym::new("+");
+    let innermost = Sym::new("x9-2");
+    let outermost = Sym::new("x0-0");
+    let mut group = c.benchmark_group("env");
+    group.bench_function("global lookup", |b| b.iter(|| global.get(black_box(&plus))));
+    group.bench_function("local lookup", |b| b.iter(|| local.get(black_box(&innermost))));
+    group.bench_function("lookup 10 frames out", |b| b.iter(|| local.get(black_box(&outermost))));
+    group.bench_function("global lookup 10 frames out", |b| b.iter(|| local.get(black_box(&plus))));
+    group.bench_function("slot 9 frames out", |b| {
+        b.iter(|| local.get_slot(black_box(9), 0, &outermost))
+    });
+    group.bench_function("new_env and set", |b| {
+        b.iter(|| {
+            let frame = black_box(&local).new_env();
+            frame.set(&innermost, MalType::Nil);
+            frame
+        })
+    });
+    group.finish();
+}
+
+/// Times calling `f` with two integers from Rust, so only the call itself is measured.
+fn bench_apply(c: &mut Criterion, name: &str, env: &Env, f: &str) {
+    let f = eval(&read_str(f.to_string()).unwrap(), env).unwrap();
+    let args: Vector<MalType> = vec![MalType::Integer(1), MalType::Integer(2)].into();
+    c.bench_function(name, |b| b.iter(|| apply(f.clone(), black_box(args.clone())).unwrap()));
+}
+
+fn calls(c: &mut Criterion) {
+    let env = repl_env();
+    bench_apply(c, "apply native +", &env, "+");
+    for (engine, label) in [(Engine::Tree, "tree"), (Engine::Vm, "vm")] {
+        let env = EnvBuilder::new().engine(engine).build();
+        bench_apply(c, &format!("apply (fn* (a b) a) ({})", label), &env, "(fn* (a b) a)");
+        bench_apply(
+            c,
+            &format!("apply (fn* (a b) (+ a b)) ({})", label),
+            &env,
+            "(fn* (a b) (+ a b))",
+        );
+    }
+    bench_eval(
+        c,
+        "1000 calls to an empty function",
+        "(def! nothing (fn* () nil))",
+        "(loop (i 0) (if (< i 1000) (do (nothing) (recur (+ i 1))) i))",
+    );
+}
+
+/// Evaluates `setup` once, then times evaluating `expr` with each engine. `big` is bound
+/// to a 30x30 `nested` value.
+fn bench_eval(c: &mut Criterion, name: &str, setup: &str, expr: &str) {
+    for (engine, label) in [(Engine::Tree, "tree"), (Engine::Vm, "vm")] {
+        let env = EnvBuilder::new().engine(engine).build();
+        env.set("big", nested(30));
+        eval(&read_str(setup.to_string()).unwrap(), &env).unwrap();
+        let form = read_str(expr.to_string()).unwrap();
+        c.bench_function(&format!("{} ({})", name, label), |b| {
+            b.iter(|| eval(black_box(&form), &env).unwrap())
+        });
+    }
+}
+
+fn evaluator(c: &mut Criterion) {
+    bench_eval(
+        c,
+        "fib 15",
+        "(def! fib (fn* (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))))",
+        "(fib 15)",
+    );
+    bench_eval(
+        c,
+        "loop 10000",
+        "(def! sum-to (fn* (n) (loop (i 0 acc 0) (if (< i n) (recur (+ i 1) (+ acc i)) acc))))",
+        "(sum-to 10000)",
+    );
+    bench_eval(
+        c,
+        "passing and printing a large value",
+        "(def! id (fn* (x) x))",
+        "(pr-str (id (id big)))",
+    );
+}
+
+criterion_group!(benches, reader, printer, env, calls, evaluator);
+criterion_main!(benches);
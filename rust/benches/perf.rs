@@ -0,0 +1,58 @@
+extern crate criterion;
+extern crate mal_rust;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use mal_rust::eval::{eval, Engine, EnvBuilder};
+use mal_rust::reader::read_all;
+use mal_rust::types::MalType;
+use std::fs;
+
+/// The perf scripts this interpreter can run. `perf1.mal` and `perf3.mal` need macros
+/// (`defmacro!`, `cond`, `->`) and the core library, which it does not have yet.
+const SCRIPTS: &[&str] = &["perf2.mal"];
+
+/// The forms of a perf script, without the `load-file`s of the core library and the
+/// `time` macro, whose body is timed here instead.
+fn script(name: &str) -> Vec<MalType> {
+    let path = format!("{}/../tests/{}", env!("CARGO_MANIFEST_DIR"), name);
+    let text = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    read_all(text)
+        .unwrap()
+        .into_iter()
+        .filter_map(|form| match form {
+            MalType::List(ref l) => match l.head() {
+                Some(MalType::Symbol(s)) if &**s == "load-file" => None,
+                Some(MalType::Symbol(s)) if &**s == "time" => l.get(1).cloned(),
+                _ => Some(form.clone()),
+            },
+            _ => Some(form),
+        })
+        .collect()
+}
+
+fn perf(c: &mut Criterion) {
+    let mut group = c.benchmark_group("perf");
+    group.sample_size(20);
+    for name in SCRIPTS {
+        let forms = script(name);
+        for (engine, label) in [(Engine::Tree, "tree"), (Engine::Vm, "vm")] {
+            group.bench_function(format!("{} ({})", name, label), |b| {
+                // a fresh environment per run, built and dropped outside the timing
+                b.iter_batched(
+                    || EnvBuilder::new().engine(engine).build(),
+                    |env| {
+                        for form in &forms {
+                            eval(form, &env).unwrap();
+                        }
+                        env
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, perf);
+criterion_main!(benches);
@@ -0,0 +1,203 @@
+use im_rc::Vector;
+use loops::check_tail_recur;
+use resolve::Frames;
+use std::mem;
+use std::rc::Rc;
+use types::EvalError::{RecurOutsideLoop, WrongArgAmount};
+use types::{MalType, MidResult, Sym};
+
+/// A form with its special forms resolved and its syntax checked, as the tree-walking
+/// evaluator runs it. Analysing a form reports errors like `(if)` before any of it runs.
+#[derive(Debug)]
+pub enum Expr {
+    /// A value that evaluates to itself, including keywords and `()`.
+    Const(MalType),
+    Symbol(Sym),
+    /// A symbol found in slot `slot` of the frame `depth` levels out, unless that slot
+    /// holds another name when it runs, when it is looked up as `Symbol` is.
+    Local { depth: usize, slot: usize, name: Sym },
+    Vector(Vec<Rc<Expr>>),
+    /// A map literal, keys and values alternating as in the form.
+    HashMap(Vec<(Rc<Expr>, Rc<Expr>)>),
+    /// A non-empty list, with the form it came from for the tracer and debugger.
+    List(MalType, Form),
+}
+
+/// What a non-empty list does when it is evaluated.
+#[derive(Debug)]
+pub enum Form {
+    Def(Sym, Rc<Expr>),
+    Let(Vec<(Sym, Rc<Expr>)>, Rc<Expr>),
+    Loop(Vec<(Sym, Rc<Expr>)>, Rc<Expr>),
+    Recur(Vec<Rc<Expr>>),
+    Do(Vec<Rc<Expr>>, Rc<Expr>),
+    If(Rc<Expr>, Rc<Expr>, Rc<Expr>),
+    Fn(Rc<Lambda>),
+    Quote(MalType),
+    Break,
+    Profile(Rc<Expr>),
+    /// A call, with the symbol the function was named by, if any.
+    Call(Option<Sym>, Vec<Rc<Expr>>),
+}
+
+/// A `fn*` form, analysed once and shared by every closure created from it.
+#[derive(Debug)]
+pub struct Lambda {
+    pub name: Option<Sym>,
+    pub params: Vector<MalType>,
+    pub body: MalType,
+    pub expr: Rc<Expr>,
+}
+
+/// Analyses a top-level form.
+pub fn analyze(form: &MalType) -> MidResult<Rc<Expr>> {
+    Analyzer::new(Frames::top_level(form)).expr(form)
+}
+
+/// Analyses the body of a function created outside the analyser, which knows the frames
+/// holding its name and parameters but not the ones it closes over.
+pub fn analyze_function(name: Option<Sym>, params: &Vector<MalType>, body: &MalType) -> MidResult<Rc<Expr>> {
+    Analyzer::new(Frames::function(name, params, body)).expr(body)
+}
+
+struct Analyzer {
+    /// Whether a `recur` here has a `loop` to jump back to.
+    in_loop: bool,
+    /// The frames around the form being analysed, where its locals are found.
+    frames: Frames,
+}
+
+impl Analyzer {
+    fn new(frames: Frames) -> Self {
+        Analyzer { in_loop: false, frames }
+    }
+
+    fn expr(&mut self, form: &MalType) -> MidResult<Rc<Expr>> {
+        let expr = match form {
+            // keywords evaluate to themselves
+            MalType::Symbol(s) if s.starts_with(':') => Expr::Const(form.clone()),
+            MalType::Symbol(s) => match self.frames.resolve(s) {
+                Some((depth, slot)) => Expr::Local {
+                    depth,
+                    slot,
+                    name: s.clone(),
+                },
+                None => Expr::Symbol(s.clone()),
+            },
+            MalType::List(l) if l.is_empty() => Expr::Const(form.clone()),
+            MalType::List(l) => {
+                let frames = self.frames.count();
+                let list = self.list(l);
+                self.frames.truncate(frames);
+                Expr::List(form.clone(), list?)
+            }
+            MalType::Vector(v) => Expr::Vector(self.nested(v.iter())?),
+            MalType::HashMap(h) => Expr::HashMap(
+                h.iter()
+                    .map(|(k, v)| Ok((self.nested_expr(k)?, self.nested_expr(v)?)))
+                    .collect::<MidResult<_>>()?,
+            ),
+            other => Expr::Const(other.clone()),
+        };
+        Ok(Rc::new(expr))
+    }
+
+    /// Analyses a form evaluated on its own rather than in tail position, where no `loop`
+    /// is in reach of a `recur`.
+    fn nested_expr(&mut self, form: &MalType) -> MidResult<Rc<Expr>> {
+        let in_loop = mem::replace(&mut self.in_loop, false);
+        let expr = self.expr(form);
+        self.in_loop = in_loop;
+        expr
+    }
+
+    fn nested<'a, I: Iterator<Item = &'a MalType>>(&mut self, forms: I) -> MidResult<Vec<Rc<Expr>>> {
+        forms.map(|f| self.nested_expr(f)).collect()
+    }
+
+    fn list(&mut self, l: &Vector<MalType>) -> MidResult<Form> {
+        let mut rest = l.iter().skip(1);
+        let head = match l.head() {
+            Some(MalType::Symbol(s)) => s.clone(),
+            _ => return Ok(Form::Call(None, self.nested(l.iter())?)),
+        };
+        let form = match head.as_str() {
+            "def!" => {
+                let key = rest.next().ok_or(WrongArgAmount)?.to_symbol()?;
+                Form::Def(key, self.nested_expr(rest.next().ok_or(WrongArgAmount)?)?)
+            }
+            "let*" => {
+                let bindings = self.bindings(rest.next().ok_or(WrongArgAmount)?)?;
+                Form::Let(bindings, self.expr(rest.next().ok_or(WrongArgAmount)?)?)
+            }
+            "loop" => {
+                let bindings = self.bindings(rest.next().ok_or(WrongArgAmount)?)?;
+                let body = rest.next().ok_or(WrongArgAmount)?;
+                check_tail_recur(body)?;
+                let in_loop = mem::replace(&mut self.in_loop, true);
+                let body = self.expr(body);
+                self.in_loop = in_loop;
+                Form::Loop(bindings, body?)
+            }
+            "recur" if !self.in_loop => return Err(RecurOutsideLoop),
+            "recur" => Form::Recur(self.nested(rest)?),
+            "do" => {
+                let body = self.nested(l.iter().skip(1).take(l.len().saturating_sub(2)))?;
+                let last = match rest.last() {
+                    Some(last) => self.expr(last)?,
+                    None => Rc::new(Expr::Const(MalType::Nil)),
+                };
+                Form::Do(body, last)
+            }
+            "if" => {
+                let cond = self.nested_expr(rest.next().ok_or(WrongArgAmount)?)?;
+                let then = self.expr(rest.next().ok_or(WrongArgAmount)?)?;
+                let otherwise = self.expr(rest.next().unwrap_or(&MalType::Nil))?;
+                Form::If(cond, then, otherwise)
+            }
+            "fn*" => {
+                let mut first = rest.next().ok_or(WrongArgAmount)?;
+                let name = match first {
+                    MalType::Symbol(name) => {
+                        first = rest.next().ok_or(WrongArgAmount)?;
+                        Some(name.clone())
+                    }
+                    _ => None,
+                };
+                let params = first.clone().to_list()?;
+                for param in params.iter() {
+                    param.to_symbol()?;
+                }
+                let body = rest.next().ok_or(WrongArgAmount)?.clone();
+                let expr = Analyzer::new(self.frames.nested(name.clone(), &params, &body)).expr(&body)?;
+                Form::Fn(Rc::new(Lambda {
+                    name,
+                    params,
+                    body,
+                    expr,
+                }))
+            }
+            "quote" => Form::Quote(rest.next().ok_or(WrongArgAmount)?.clone()),
+            "break!" => Form::Break,
+            "profile" => Form::Profile(self.expr(rest.next().ok_or(WrongArgAmount)?)?),
+            _ => Form::Call(Some(head), self.nested(l.iter())?),
+        };
+        Ok(form)
+    }
+
+    /// Analyses `let*` or `loop` bindings, which may be a list or a vector, in the frame
+    /// they are bound in.
+    fn bindings(&mut self, bindings: &MalType) -> MidResult<Vec<(Sym, Rc<Expr>)>> {
+        let mut analysed = Vec::new();
+        let mut bindings = bindings.clone().to_list()?.into_iter();
+        self.frames.push();
+        while let Some(name) = bindings.next() {
+            let name = name.to_symbol()?;
+            let value = self.nested_expr(&bindings.next().ok_or(WrongArgAmount)?)?;
+            self.frames.bind(&name);
+            analysed.push((name, value));
+        }
+        self.frames.close();
+        Ok(analysed)
+    }
+}
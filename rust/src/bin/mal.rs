@@ -0,0 +1,284 @@
+extern crate log;
+extern crate mal_rust;
+
+use log::LevelFilter;
+use mal_rust::eval::{eval, load_file, run_script, safe_point, with_eval_stack};
+use mal_rust::eval::{Engine, EnvBuilder, ENGINE_VAR, EVAL_STACK_MAX_DEPTH};
+use mal_rust::formatter;
+use mal_rust::logger;
+use mal_rust::logger::LogConfig;
+use mal_rust::profiler;
+use mal_rust::reader::{read_all, Reader};
+use mal_rust::repl;
+use mal_rust::repl::ReplOptions;
+use mal_rust::types::{EvalError, MalType};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+const USAGE: &str = "Usage: mal [OPTIONS] [COMMAND]
+
+Commands:
+  repl                   Start the interactive REPL (the default)
+  run <file> [args...]   Run a script with *ARGV* bound to args; - reads it from stdin
+  eval -e <expr>         Evaluate the forms in expr and print the last result
+  check <file>...        Parse files without evaluating them
+  fmt <file>...          Print files with their forms laid out canonically
+
+Options:
+  --log-level <level>    off, error, warn, info, debug or trace [default: error]
+                         MAL_LOG sets the level, format=plain|timestamp|json and file=<path>
+  --engine <engine>      tree or vm (bytecode) [default: $MAL_ENGINE or tree]
+  --trace                Trace every evaluated form to stderr, like (trace! true)
+  --profile              Print the time and calls spent per function to stderr on exit
+  --profile-folded <path>
+                         Write folded stacks for flamegraph.pl or inferno on exit
+  --prelude <file>       Load a mal file before running the command
+  --history <path>       Where the REPL keeps its history
+  -h, --help             Print this help";
+
+const FMT_WIDTH: usize = 80;
+
+enum Command {
+    Help,
+    Repl,
+    Run(String, Vec<String>),
+    Eval(String),
+    Check(Vec<String>),
+    Fmt(Vec<String>),
+}
+
+struct Options {
+    command: Command,
+    log_level: Option<LevelFilter>,
+    engine: Option<Engine>,
+    trace: bool,
+    profile: bool,
+    profile_folded: Option<PathBuf>,
+    prelude: Option<String>,
+    history: Option<PathBuf>,
+}
+
+fn main() {
+    let options = match parse_args(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("mal: {}\n\n{}", e, USAGE);
+            process::exit(2)
+        }
+    };
+    process::exit(with_eval_stack(move || run(options)))
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, String> {
+    let mut options = Options {
+        command: Command::Repl,
+        log_level: None,
+        engine: None,
+        trace: false,
+        profile: false,
+        profile_folded: None,
+        prelude: None,
+        history: None,
+    };
+    let mut command: Option<String> = None;
+    let mut operands = Vec::new();
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "-h" | "--help" => options.command = Command::Help,
+            "--log-level" => {
+                options.log_level = Some(logger::parse_level(&value("--log-level")?)?);
+            }
+            "--engine" => options.engine = Some(value("--engine")?.parse()?),
+            "--trace" => options.trace = true,
+            "--profile" => options.profile = true,
+            "--profile-folded" => {
+                options.profile_folded = Some(PathBuf::from(value("--profile-folded")?))
+            }
+            "--prelude" => options.prelude = Some(value("--prelude")?),
+            "--history" => options.history = Some(PathBuf::from(value("--history")?)),
+            "-e" | "--expr" => operands.push(value("-e")?),
+            _ if command.is_none() => {
+                command = Some(arg);
+                // everything after the script belongs to *ARGV*
+                if command.as_deref() == Some("run") {
+                    operands.extend(args.by_ref());
+                }
+            }
+            _ => operands.push(arg),
+        }
+    }
+    if let Command::Help = options.command {
+        return Ok(options);
+    }
+    options.command = match command.as_deref() {
+        None | Some("repl") if operands.is_empty() => Command::Repl,
+        Some("run") if !operands.is_empty() => {
+            let script = operands.remove(0);
+            Command::Run(script, operands)
+        }
+        Some("eval") if operands.len() == 1 => Command::Eval(operands.remove(0)),
+        Some("check") if !operands.is_empty() => Command::Check(operands),
+        Some("fmt") if !operands.is_empty() => Command::Fmt(operands),
+        Some(c @ "repl") | Some(c @ "run") | Some(c @ "eval") | Some(c @ "check")
+        | Some(c @ "fmt") => return Err(format!("wrong arguments for {}", c)),
+        None => return Err("unexpected arguments".to_string()),
+        Some(c) => return Err(format!("unknown command: {}", c)),
+    };
+    Ok(options)
+}
+
+fn run(options: Options) -> i32 {
+    let profile = options.profile;
+    let profile_folded = options.profile_folded.clone();
+    if profile || profile_folded.is_some() {
+        profiler::start();
+    }
+    let code = run_command(options);
+    if profile || profile_folded.is_some() {
+        let recorded = profiler::stop();
+        if profile {
+            eprintln!("{}", recorded.report());
+        }
+        if let Some(path) = profile_folded {
+            let written = fs::File::create(&path).and_then(|file| {
+                let mut out = BufWriter::new(file);
+                recorded.write_folded(&mut out)?;
+                out.flush()
+            });
+            if let Err(e) = written {
+                eprintln!("mal: {}: {}", path.display(), e);
+                return 1;
+            }
+        }
+    }
+    code
+}
+
+fn run_command(options: Options) -> i32 {
+    let mut log_config = match LogConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("mal: {}: {}", logger::LOG_VAR, e);
+            return 2;
+        }
+    };
+    log_config.level = match options.log_level {
+        Some(level) => level,
+        None if env::var_os(logger::LOG_VAR).is_some() => log_config.level,
+        None => LevelFilter::Error,
+    };
+    if let Err(e) = logger::init_with(log_config) {
+        eprintln!("mal: {}", e);
+        return 2;
+    }
+    let engine = match options.engine {
+        Some(engine) => Some(engine),
+        None => match Engine::from_env() {
+            Ok(engine) => engine,
+            Err(e) => {
+                eprintln!("mal: {}: {}", ENGINE_VAR, e);
+                return 2;
+            }
+        },
+    };
+    let mut builder = EnvBuilder::new().max_depth(EVAL_STACK_MAX_DEPTH);
+    if let Some(engine) = engine {
+        builder = builder.engine(engine);
+    }
+    let env = builder.build();
+    logger::set_tracing(options.trace);
+    if let Some(prelude) = &options.prelude {
+        if let Err(e) = load_file(prelude, &env) {
+            eprintln!("{}: {}", prelude, e);
+            return 1;
+        }
+    }
+    match options.command {
+        Command::Help => {
+            println!("{}", USAGE);
+            0
+        }
+        Command::Repl => {
+            let mut repl_options = ReplOptions::default();
+            if let Some(history) = options.history {
+                repl_options.history = Some(history);
+            }
+            repl::run(env, &repl_options);
+            0
+        }
+        Command::Run(script, args) => report(run_script(&script, args, &env)),
+        Command::Eval(expr) => {
+            let result = read_all(expr)
+                .map_err(EvalError::from)
+                .and_then(|forms| {
+                    forms
+                        .into_iter()
+                        .try_fold(MalType::Nil, |_, form| {
+                            let value = eval(&form, &env)?;
+                            safe_point();
+                            Ok(value)
+                        })
+                });
+            match result {
+                Ok(value) => {
+                    println!("{}", value.pr_str(true));
+                    0
+                }
+                Err(e) => report(Err(e)),
+            }
+        }
+        Command::Check(files) => {
+            let failures = files
+                .iter()
+                .filter(|file| match parse_file(file) {
+                    Ok(forms) => {
+                        println!("{}: ok, {} forms", file, forms.len());
+                        false
+                    }
+                    Err(e) => {
+                        eprintln!("{}: {}", file, e);
+                        true
+                    }
+                })
+                .count();
+            if failures == 0 {
+                0
+            } else {
+                1
+            }
+        }
+        Command::Fmt(files) => {
+            for file in files {
+                let formatted = fs::read_to_string(&file)
+                    .map_err(|_| EvalError::InvalidFile(file.clone()))
+                    .and_then(|source| Ok(formatter::format(&source, FMT_WIDTH)?));
+                match formatted {
+                    Ok(text) => println!("{}", text),
+                    Err(e) => {
+                        eprintln!("{}: {}", file, e);
+                        return 1;
+                    }
+                }
+            }
+            0
+        }
+    }
+}
+
+fn parse_file(file: &str) -> Result<Vec<MalType>, EvalError> {
+    let file = fs::File::open(file).map_err(|_| EvalError::InvalidFile(file.to_string()))?;
+    Ok(Reader::new(file).collect::<Result<Vec<MalType>, _>>()?)
+}
+
+fn report(result: Result<MalType, EvalError>) -> i32 {
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
@@ -4,21 +4,20 @@ use std::io;
 use std::io::{stdout, Write};
 
 fn read(text: String) -> String {
-    return text;
+    text
 }
 fn eval(text: String) -> String {
-    return text;
+    text
 }
 
 fn print(text: String) -> String {
-    return text;
+    text
 }
 
 fn rep(text: String) -> String {
     let text = read(text);
     let text = eval(text);
-    let text = print(text);
-    return text;
+    print(text)
 }
 fn main() {
     let standard_input = io::stdin();
@@ -23,14 +23,8 @@ fn main() {
     let mut rl = rustyline::Editor::<()>::new().unwrap();
     File::create("history.txt").unwrap();
     rl.load_history("history.txt").unwrap();
-    loop {
-        let input = match rl.readline("user> ") {
-            Ok(i) => {
-                rl.add_history_entry(i.as_str());
-                i
-            }
-            Err(_) => break,
-        };
+    while let Ok(input) = rl.readline("user> ") {
+        rl.add_history_entry(input.as_str());
         let result = rep(input);
         match result {
             Ok(a) => {
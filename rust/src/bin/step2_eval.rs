@@ -10,7 +10,6 @@ use mal_rust::types::MalType::Nil;
 use mal_rust::types::*;
 use std::error;
 use std::fs::File;
-use std::iter::FromIterator;
 
 type ResultBox<T> = std::result::Result<T, Box<dyn error::Error>>;
 fn read(input: String) -> ParseResult {
@@ -23,22 +22,19 @@ fn eval(ast: MalType, env: &Env) -> EvalResult {
             match l.next() {
                 None => Ok(ast),
                 Some(head) => {
-                    if let Ok(symbol) = head.clone().to_symbol() {
-                        match symbol.as_str() {
-                            "def!" => {
-                                let mut l = list.iter();
-                                l.next().expect("should have a value");
-                                let key = l
-                                    .next()
-                                    .ok_or(EvalError::WrongArgAmount)?
-                                    .clone()
-                                    .to_symbol()?;
-                                let value =
-                                    eval(l.next().ok_or(EvalError::WrongArgAmount)?.clone(), env)?;
-                                env.set(key, value.clone());
-                                return Ok(Nil);
-                            }
-                            _ => {}
+                    if let Ok(symbol) = head.to_symbol() {
+                        if symbol.as_str() == "def!" {
+                            let mut l = list.iter();
+                            l.next().expect("should have a value");
+                            let key = l
+                                .next()
+                                .ok_or(EvalError::WrongArgAmount)?
+                                .clone()
+                                .to_symbol()?;
+                            let value =
+                                eval(l.next().ok_or(EvalError::WrongArgAmount)?.clone(), env)?;
+                            env.set(key, value.clone());
+                            return Ok(Nil);
                         }
                     }
                     let mut new_list = eval_ast(ast, env)?.to_list().expect("should be a list");
@@ -52,7 +48,7 @@ fn eval(ast: MalType, env: &Env) -> EvalResult {
 }
 fn eval_ast(ast: MalType, env: &Env) -> EvalResult {
     match ast {
-        MalType::Symbol(s) => env.get(&s).ok_or(SymbolNotFound(s)),
+        MalType::Symbol(s) => env.get(&s).ok_or_else(|| SymbolNotFound(s.to_string())),
         MalType::List(l) => {
             let mut vec = Vector::new();
             for i in l.into_iter() {
@@ -77,7 +73,7 @@ fn eval_ast(ast: MalType, env: &Env) -> EvalResult {
                 let value = eval(value.clone(), env)?;
                 map.insert(k, value);
             }
-            Ok(MalType::HashMap(map.into()))
+            Ok(MalType::HashMap(map))
         }
         other => Ok(other),
     }
@@ -96,14 +92,8 @@ fn main() {
     let env = default_env();
     File::create("history.txt").unwrap();
     rl.load_history("history.txt").unwrap();
-    loop {
-        let input = match rl.readline("user> ") {
-            Ok(i) => {
-                rl.add_history_entry(i.as_str());
-                i
-            }
-            Err(_) => break,
-        };
+    while let Ok(input) = rl.readline("user> ") {
+        rl.add_history_entry(input.as_str());
         let result = rep(input, &env);
         match result {
             Ok(a) => {
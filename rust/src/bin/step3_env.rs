@@ -21,7 +21,7 @@ fn eval(ast: MalType, env: &Env) -> EvalResult {
             match l.next() {
                 None => Ok(ast),
                 Some(head) => {
-                    if let Ok(symbol) = head.clone().to_symbol() {
+                    if let Ok(symbol) = head.to_symbol() {
                         match symbol.as_str() {
                             "def!" => {
                                 let key = l
@@ -43,7 +43,7 @@ fn eval(ast: MalType, env: &Env) -> EvalResult {
                                     .to_list()?
                                     .into_iter();
                                 while let Some(k) = first_parameter.next() {
-                                    let key = k.clone().to_symbol()?;
+                                    let key = k.to_symbol()?;
                                     let value = eval(
                                         first_parameter.next().ok_or(WrongArgAmount)?.clone(),
                                         &new_env,
@@ -66,7 +66,7 @@ fn eval(ast: MalType, env: &Env) -> EvalResult {
 }
 fn eval_ast(ast: MalType, env: &Env) -> EvalResult {
     match ast {
-        MalType::Symbol(s) => env.get(&s).ok_or(SymbolNotFound(s)),
+        MalType::Symbol(s) => env.get(&s).ok_or_else(|| SymbolNotFound(s.to_string())),
         MalType::List(l) => {
             let mut vec = Vector::new();
             for i in l.into_iter() {
@@ -91,7 +91,7 @@ fn eval_ast(ast: MalType, env: &Env) -> EvalResult {
                 let value = eval(value.clone(), env)?;
                 map.insert(k, value);
             }
-            Ok(MalType::HashMap(map.into()))
+            Ok(MalType::HashMap(map))
         }
         other => Ok(other),
     }
@@ -110,14 +110,8 @@ fn main() {
     let env = default_env();
     File::create("history.txt").unwrap();
     rl.load_history("history.txt").unwrap();
-    loop {
-        let input = match rl.readline("user> ") {
-            Ok(i) => {
-                rl.add_history_entry(i.as_str());
-                i
-            }
-            Err(_) => break,
-        };
+    while let Ok(input) = rl.readline("user> ") {
+        rl.add_history_entry(input.as_str());
         let result = rep(input, &env);
         match result {
             Ok(a) => {
@@ -1,10 +1,9 @@
-extern crate env_logger;
 extern crate im_rc;
 extern crate log;
+#[macro_use]
 extern crate mal_rust;
 
 use im_rc::{HashMap, Vector};
-use log::warn;
 use mal_rust::env::Env;
 use mal_rust::functions::Functions::NonNative;
 use mal_rust::functions::{default_env, default_env_non_native};
@@ -27,8 +26,8 @@ fn eval(ast: MalType, env: &Env) -> EvalResult {
         MalType::List(list) => match list.head() {
             None => list.into(),
             Some(head) => {
-                warn!("{}", MalType::List(list.clone()));
-                if let Ok(symbol) = head.clone().to_symbol() {
+                mal_trace!("{}", MalType::List(list.clone()));
+                if let Ok(symbol) = head.to_symbol() {
                     match symbol.as_str() {
                         "def!" => {
                             let mut l = list.into_iter();
@@ -40,7 +39,7 @@ fn eval(ast: MalType, env: &Env) -> EvalResult {
                                 .to_symbol()?;
                             let value =
                                 eval(l.next().ok_or(EvalError::WrongArgAmount)?.clone(), env)?;
-                            warn!("set {} to {}", key.clone(), value.clone());
+                            mal_trace!("set {} to {}", key.clone(), value.clone());
                             env.set(key, value.clone());
                             value
                         }
@@ -55,7 +54,7 @@ fn eval(ast: MalType, env: &Env) -> EvalResult {
                                 .to_list()?
                                 .into_iter();
                             while let Some(k) = first_parameter.next() {
-                                let key = k.clone().to_symbol()?;
+                                let key = k.to_symbol()?;
                                 let value = eval(
                                     first_parameter.next().ok_or(WrongArgAmount)?.clone(),
                                     &new_env,
@@ -76,7 +75,7 @@ fn eval(ast: MalType, env: &Env) -> EvalResult {
                             let mut l = list;
                             l.pop_front().unwrap();
                             let cond = eval(l.pop_front().ok_or(WrongArgAmount)?, env)?.to_bool();
-                            warn!(
+                            mal_trace!(
                                 "(if {} {} {})",
                                 cond.clone(),
                                 l[0].clone(),
@@ -100,7 +99,7 @@ fn eval(ast: MalType, env: &Env) -> EvalResult {
                             let body = l.next().ok_or(WrongArgAmount)?;
                             let env_copy = env.clone();
                             MalType::Function(NonNative(Rc::new(move |m: Vector<MalType>| {
-                                let env = env_copy.new_bind(variables.clone(), m)?;
+                                let env = env_copy.new_bind(&variables, m)?;
                                 eval(body.clone(), &env)
                             })))
                         }
@@ -120,19 +119,19 @@ fn call_with_first_as_func(list: Vector<MalType>, env: &Env) -> EvalResult {
     let mut new_list = eval_ast(list.into(), env)?
         .to_list()
         .expect("should be a list");
-    warn!("{}", MalType::List(new_list.clone()));
+    mal_trace!("{}", MalType::List(new_list.clone()));
     let first = new_list.pop_front().unwrap().to_function()?;
     let result = first.call(new_list);
-    warn!("{}", result.clone()?);
+    mal_trace!("{}", result.clone()?);
     result
 }
 fn eval_ast(ast: MalType, env: &Env) -> EvalResult {
     let result = match ast {
         MalType::Symbol(s) => {
-            let result = env.get(&s).ok_or(SymbolNotFound(s.clone()))?;
+            let result = env.get(&s).ok_or_else(|| SymbolNotFound(s.to_string()))?;
             match &result {
                 MalType::Function(_) => {}
-                _ => warn!("{} -> {}", s.clone(), result.clone()),
+                _ => mal_trace!("{} -> {}", s.clone(), result.clone()),
             }
             Ok(result)
         }
@@ -160,7 +159,7 @@ fn eval_ast(ast: MalType, env: &Env) -> EvalResult {
                 let value = eval(value.clone(), env)?;
                 map.insert(k, value);
             }
-            Ok(MalType::HashMap(map.into()))
+            Ok(MalType::HashMap(map))
         }
         other => Ok(other),
     };
@@ -176,7 +175,7 @@ fn rep(text: String, env: &Env) -> ResultBox<String> {
 }
 
 fn main() {
-    println!("{}", "hello".to_string());
+    println!("hello");
     let mut rl = rustyline::Editor::<()>::new().unwrap();
     let env = default_env();
     default_env_non_native().into_iter().for_each(|s| {
@@ -185,14 +184,8 @@ fn main() {
     File::create("history.txt").unwrap();
     rl.load_history("history.txt").unwrap();
     logger::init().unwrap();
-    loop {
-        let input = match rl.readline("user> ") {
-            Ok(i) => {
-                rl.add_history_entry(i.as_str());
-                i
-            }
-            Err(_) => break,
-        };
+    while let Ok(input) = rl.readline("user> ") {
+        rl.add_history_entry(input.as_str());
         let result = rep(input, &env);
         match result {
             Ok(a) => {
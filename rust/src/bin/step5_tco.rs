@@ -1,19 +1,20 @@
-extern crate env_logger;
 extern crate im_rc;
 extern crate log;
+#[macro_use]
 extern crate mal_rust;
 
 use im_rc::{HashMap, Vector};
-use log::warn;
 use mal_rust::env::Env;
 use mal_rust::functions::{default_env, default_env_non_native, InnerFunction};
 use mal_rust::logger;
+use mal_rust::loops::{check_tail_recur, Loop};
 use mal_rust::reader::*;
-use mal_rust::types::EvalError::{SymbolNotFound, WrongArgAmount};
+use mal_rust::types::EvalError::{RecurOutsideLoop, SymbolNotFound, WrongArgAmount};
 use mal_rust::types::MalType::{List, Nil};
 use mal_rust::types::*;
 use std::error;
 use std::fs::File;
+use std::rc::Rc;
 
 type ResultBox<T> = std::result::Result<T, Box<dyn error::Error>>;
 fn read(input: String) -> ParseResult {
@@ -21,12 +22,13 @@ fn read(input: String) -> ParseResult {
 }
 
 fn eval(mut ast: MalType, mut env: Env) -> EvalResult {
+    let mut current_loop: Option<Rc<Loop>> = None;
     loop {
         ast = match ast {
             MalType::List(list) => match list.head() {
                 Some(head) => {
-                    warn!("{}", MalType::List(list.clone()));
-                    if let Ok(symbol) = head.clone().to_symbol() {
+                    mal_trace!("{}", MalType::List(list.clone()));
+                    if let Ok(symbol) = head.to_symbol() {
                         match symbol.as_str() {
                             "def!" => {
                                 let mut l = list.into_iter();
@@ -40,7 +42,7 @@ fn eval(mut ast: MalType, mut env: Env) -> EvalResult {
                                     l.next().ok_or(EvalError::WrongArgAmount)?.clone(),
                                     env.clone(),
                                 )?;
-                                warn!("set {} to {}", key.clone(), value.clone());
+                                mal_trace!("set {} to {}", key.clone(), value.clone());
                                 env.set(key, value.clone());
                                 return Ok(value);
                             }
@@ -51,7 +53,7 @@ fn eval(mut ast: MalType, mut env: Env) -> EvalResult {
                                 let mut first_parameter =
                                     l.next().ok_or(WrongArgAmount)?.to_list()?.into_iter();
                                 while let Some(k) = first_parameter.next() {
-                                    let key = k.clone().to_symbol()?;
+                                    let key = k.to_symbol()?;
                                     let value = eval(
                                         first_parameter.next().ok_or(WrongArgAmount)?.clone(),
                                         env.clone(),
@@ -60,6 +62,39 @@ fn eval(mut ast: MalType, mut env: Env) -> EvalResult {
                                 }
                                 l.next().ok_or(WrongArgAmount)?
                             }
+                            "loop" => {
+                                let mut l = list.into_iter();
+                                l.next().unwrap();
+                                let bindings = l.next().ok_or(WrongArgAmount)?.to_list()?;
+                                let body = l.next().ok_or(WrongArgAmount)?;
+                                check_tail_recur(&body)?;
+                                let outer = env.clone();
+                                env = env.new_env();
+                                let mut params = Vector::new();
+                                let mut bindings = bindings.into_iter();
+                                while let Some(k) = bindings.next() {
+                                    let key = k.to_symbol()?;
+                                    let value = eval(
+                                        bindings.next().ok_or(WrongArgAmount)?,
+                                        env.clone(),
+                                    )?;
+                                    env.set(&key, value);
+                                    params.push_back(MalType::Symbol(key));
+                                }
+                                current_loop = Some(Rc::new(Loop {
+                                    params,
+                                    body: body.clone(),
+                                    env: outer,
+                                }));
+                                body
+                            }
+                            "recur" => {
+                                let target = current_loop.clone().ok_or(RecurOutsideLoop)?;
+                                let args = eval_ast(List(list.skip(1)), &env)?.to_list()?;
+                                let (new_env, body) = target.recur(args)?;
+                                env = new_env;
+                                body
+                            }
                             "do" => {
                                 let mut l = list.into_iter();
                                 l.next().unwrap();
@@ -73,7 +108,7 @@ fn eval(mut ast: MalType, mut env: Env) -> EvalResult {
                                 l.pop_front().unwrap();
                                 let cond = eval(l.pop_front().ok_or(WrongArgAmount)?, env.clone())?
                                     .to_bool();
-                                warn!(
+                                mal_trace!(
                                     "(if {} {} {})",
                                     cond.clone(),
                                     l[0].clone(),
@@ -92,27 +127,30 @@ fn eval(mut ast: MalType, mut env: Env) -> EvalResult {
                             "fn*" => {
                                 let mut l = list.into_iter();
                                 l.next().unwrap();
-                                let variables = l.next().ok_or(WrongArgAmount)?.to_list()?;
-                                let body = l.next().ok_or(WrongArgAmount)?;
-                                let env_copy = env.clone();
-                                let fun = InnerFunction{
-                                    ast: body,
-                                    params: variables,
-                                    env: env_copy,
+                                let mut first = l.next().ok_or(WrongArgAmount)?;
+                                let name = match first {
+                                    MalType::Symbol(name) => {
+                                        first = l.next().ok_or(WrongArgAmount)?;
+                                        Some(name)
+                                    }
+                                    _ => None,
                                 };
-                                return fun.into()
+                                let variables = first.to_list()?;
+                                let body = l.next().ok_or(WrongArgAmount)?;
+                                return Ok(InnerFunction::closure(name, variables, body, &env));
                             }
                             _ => {
                                 let mut new_list = eval_ast(list.into(), &env)?
                                     .to_list()
                                     .expect("should be a list");
-                                warn!("{}", MalType::List(new_list.clone()));
+                                mal_trace!("{}", MalType::List(new_list.clone()));
                                 match new_list.pop_front().unwrap() {
                                     MalType::Function(f) => {
                                         return f.call(new_list)
                                     }
                                     MalType::NonNativeFunction(f) => {
-                                        env = env.new_bind(f.params.clone(), new_list)?;
+                                        env = f.env.new_bind(&f.params, new_list)?;
+                                        current_loop = None;
                                         f.ast.clone()
                                     }
                                     other => return Err(EvalError::InvalidType("Function".to_string(), other.type_string())),
@@ -123,13 +161,14 @@ fn eval(mut ast: MalType, mut env: Env) -> EvalResult {
                         let mut new_list = eval_ast(list.into(), &env)?
                             .to_list()
                             .expect("should be a list");
-                        warn!("{}", MalType::List(new_list.clone()));
+                        mal_trace!("{}", MalType::List(new_list.clone()));
                         match new_list.pop_front().unwrap() {
                             MalType::Function(f) => {
                                 return f.call(new_list)
                             }
                             MalType::NonNativeFunction(f) => {
-                                env = f.env.new_bind(f.params.clone(), new_list)?;
+                                env = f.env.new_bind(&f.params, new_list)?;
+                                current_loop = None;
                                 f.ast.clone()
                             }
                             other => return Err(EvalError::InvalidType("Function".to_string(), other.type_string())),
@@ -146,10 +185,10 @@ fn eval(mut ast: MalType, mut env: Env) -> EvalResult {
 fn eval_ast(ast: MalType, env: &Env) -> EvalResult {
     let result = match ast {
         MalType::Symbol(s) => {
-            let result = env.get(&s).ok_or(SymbolNotFound(s.clone()))?;
+            let result = env.get(&s).ok_or_else(|| SymbolNotFound(s.to_string()))?;
             match &result {
                 MalType::Function(_) => {}
-                _ => warn!("{} -> {}", s.clone(), result.clone()),
+                _ => mal_trace!("{} -> {}", s.clone(), result.clone()),
             }
             Ok(result)
         }
@@ -177,7 +216,7 @@ fn eval_ast(ast: MalType, env: &Env) -> EvalResult {
                 let value = eval(value.clone(), env.clone())?;
                 map.insert(k, value);
             }
-            Ok(MalType::HashMap(map.into()))
+            Ok(MalType::HashMap(map))
         }
         other => Ok(other),
     };
@@ -193,7 +232,7 @@ fn rep(text: String, env: Env) -> ResultBox<String> {
 }
 
 fn main() {
-    println!("{}", "hello".to_string());
+    println!("hello");
     let mut rl = rustyline::Editor::<()>::new().unwrap();
     let env = default_env();
     default_env_non_native().into_iter().for_each(|s| {
@@ -202,14 +241,8 @@ fn main() {
     File::create("history.txt").unwrap();
     rl.load_history("history.txt").unwrap();
     logger::init().unwrap();
-    loop {
-        let input = match rl.readline("user> ") {
-            Ok(i) => {
-                rl.add_history_entry(i.as_str());
-                i
-            }
-            Err(_) => break,
-        };
+    while let Ok(input) = rl.readline("user> ") {
+        rl.add_history_entry(input.as_str());
         let result = rep(input, env.clone());
         match result {
             Ok(a) => {
@@ -0,0 +1,360 @@
+use im_rc::Vector;
+use loops::check_tail_recur;
+use resolve::Frames;
+use std::rc::Rc;
+use types::EvalError::{RecurOutsideLoop, WrongArgAmount};
+use types::{EvalError, MalType, MidResult, Sym};
+
+/// One instruction of the stack machine in `vm`.
+#[derive(Debug)]
+pub enum Op {
+    /// Pushes a value.
+    Const(MalType),
+    /// Pushes the value bound to a symbol.
+    Get(Sym),
+    /// Pushes the value in slot `slot` of the frame `depth` levels out, falling back to
+    /// looking `name` up if that slot holds another name.
+    GetLocal { depth: usize, slot: usize, name: Sym },
+    /// Binds the value on top of the stack in the current environment, leaving it there.
+    Def(Sym),
+    Pop,
+    Jump(usize),
+    /// Pops a value and jumps if it is `nil` or `false`.
+    JumpIfFalse(usize),
+    /// Enters a new environment frame, as `let*` and `loop` do.
+    PushScope,
+    /// Pops a value and binds it in the current frame.
+    Bind(Sym),
+    /// Leaves the frame entered by the matching `PushScope`.
+    PopScope,
+    /// Creates a closure over the current environment.
+    Closure(Rc<Proto>),
+    /// Calls the function below the top `argc` values with them as arguments. Holds the
+    /// symbol the function was called by, which the tracer and profiler report.
+    Call(usize, Option<Sym>),
+    /// Like `Call`, but reuses the current frame when calling a mal function.
+    TailCall(usize, Option<Sym>),
+    /// Rebinds the loop names to the top `argc` values and jumps back to the loop body.
+    Recur {
+        argc: usize,
+        start: usize,
+        scope: usize,
+        params: Vector<MalType>,
+    },
+    /// Charges the sandbox one unit of fuel for a form about to be evaluated.
+    Fuel,
+    /// Charges the sandbox for a collection about to be built.
+    Allocate(usize),
+    MakeVector(usize),
+    /// Builds a map from the top `2 * n` values, keys and values alternating.
+    MakeMap(usize),
+    /// Fails unless the value on top of the stack can be a map key.
+    CheckKey,
+    /// Evaluates a form with the tree-walking evaluator, for the forms the debugger and
+    /// profiler handle.
+    Tree(MalType),
+    /// Fails with an error found while compiling a function body the compiler did not see
+    /// created, on its first call, as analysing it would.
+    Fail(EvalError),
+    Return,
+}
+
+/// Compiled code for a top-level form or a function body.
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub ops: Vec<Op>,
+}
+
+/// A `fn*` form, compiled once and shared by every closure created from it.
+#[derive(Debug)]
+pub struct Proto {
+    pub name: Option<Sym>,
+    pub params: Vector<MalType>,
+    pub body: MalType,
+    pub chunk: Rc<Chunk>,
+}
+
+/// Compiles a form so that running it returns its value. Like analysis, fails on a
+/// malformed special form anywhere in it, nested `fn*` bodies included, before any of it
+/// runs.
+pub fn compile(form: &MalType) -> MidResult<Chunk> {
+    Compiler::new(Frames::top_level(form)).finish(form)
+}
+
+/// Compiles the body of a function created outside the compiler, which knows the
+/// frames holding its name and parameters but not the ones it closes over.
+pub fn compile_function(name: Option<Sym>, params: &Vector<MalType>, body: &MalType) -> Chunk {
+    Compiler::new(Frames::function(name, params, body))
+        .finish(body)
+        .unwrap_or_else(|e| Chunk { ops: vec![Op::Fail(e)] })
+}
+
+/// Whether `form` is handed to the tree-walking evaluator, as `Op::Tree`.
+fn runs_on_tree(form: &MalType) -> bool {
+    match form {
+        MalType::List(l) => matches!(l.head(), Some(MalType::Symbol(s)) if matches!(s.as_str(), "break!" | "profile")),
+        _ => false,
+    }
+}
+
+struct LoopTarget {
+    start: usize,
+    scope: usize,
+    params: Vector<MalType>,
+}
+
+struct Compiler {
+    ops: Vec<Op>,
+    /// How many `PushScope`s enclose the code being compiled.
+    scopes: usize,
+    /// The innermost `loop` a `recur` jumps back to.
+    target: Option<LoopTarget>,
+    /// The frames around the code being compiled.
+    frames: Frames,
+    /// The first error found, which fails the whole form.
+    error: Option<EvalError>,
+}
+
+impl Compiler {
+    fn new(frames: Frames) -> Self {
+        Compiler {
+            ops: Vec::new(),
+            scopes: 0,
+            target: None,
+            frames,
+            error: None,
+        }
+    }
+
+    fn finish(mut self, form: &MalType) -> MidResult<Chunk> {
+        self.expr(form, true);
+        self.ops.push(Op::Return);
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(Chunk { ops: self.ops }),
+        }
+    }
+
+    /// The instruction that loads `name`: a slot when every frame between here and the
+    /// one binding it is known, a lookup by name otherwise.
+    fn load(&self, name: Sym) -> Op {
+        match self.frames.resolve(&name) {
+            Some((depth, slot)) => Op::GetLocal { depth, slot, name },
+            None => Op::Get(name),
+        }
+    }
+
+    fn push_scope(&mut self) {
+        self.emit(Op::PushScope);
+        self.scopes += 1;
+        self.frames.push();
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.ops.push(op);
+        self.ops.len() - 1
+    }
+
+    fn patch(&mut self, at: usize) {
+        let to = self.ops.len();
+        match &mut self.ops[at] {
+            Op::Jump(target) | Op::JumpIfFalse(target) => *target = to,
+            op => panic!("cannot patch {:?}", op),
+        }
+    }
+
+    fn expr(&mut self, form: &MalType, tail: bool) {
+        // every form evaluated costs fuel, as in the tree walker, which charges for the
+        // forms handed to it itself
+        if !runs_on_tree(form) {
+            self.emit(Op::Fuel);
+        }
+        match form {
+            // keywords evaluate to themselves
+            MalType::Symbol(s) if s.starts_with(':') => {
+                self.emit(Op::Const(form.clone()));
+            }
+            MalType::Symbol(s) => {
+                let op = self.load(s.clone());
+                self.emit(op);
+            }
+            MalType::List(l) if l.is_empty() => {
+                self.emit(Op::Const(form.clone()));
+            }
+            MalType::List(l) => {
+                let scopes = self.scopes;
+                let frames = self.frames.count();
+                if let Err(e) = self.list(form, l, tail) {
+                    self.error.get_or_insert(e);
+                }
+                self.scopes = scopes;
+                self.frames.truncate(frames);
+            }
+            MalType::Vector(v) => {
+                self.emit(Op::Allocate(v.len()));
+                v.iter().for_each(|x| self.expr(x, false));
+                self.emit(Op::MakeVector(v.len()));
+            }
+            MalType::HashMap(h) => {
+                self.emit(Op::Allocate(h.len() * 2));
+                for (k, v) in h.iter() {
+                    self.expr(k, false);
+                    self.emit(Op::CheckKey);
+                    self.expr(v, false);
+                }
+                self.emit(Op::MakeMap(h.len()));
+            }
+            other => {
+                self.emit(Op::Const(other.clone()));
+            }
+        }
+    }
+
+    fn list(&mut self, form: &MalType, l: &Vector<MalType>, tail: bool) -> MidResult<()> {
+        let mut rest = l.iter().skip(1);
+        if let Some(MalType::Symbol(s)) = l.head() {
+            match s.as_str() {
+                "def!" => {
+                    let key = rest.next().ok_or(WrongArgAmount)?.to_symbol()?;
+                    self.expr(rest.next().ok_or(WrongArgAmount)?, false);
+                    self.emit(Op::Def(key));
+                    return Ok(());
+                }
+                "let*" => {
+                    let bindings = rest.next().ok_or(WrongArgAmount)?.clone().to_list()?;
+                    self.push_scope();
+                    self.bindings(bindings)?;
+                    self.expr(rest.next().ok_or(WrongArgAmount)?, tail);
+                    self.emit(Op::PopScope);
+                    return Ok(());
+                }
+                "loop" => {
+                    let bindings = rest.next().ok_or(WrongArgAmount)?.clone().to_list()?;
+                    let body = rest.next().ok_or(WrongArgAmount)?;
+                    check_tail_recur(body)?;
+                    let scope = self.scopes;
+                    self.push_scope();
+                    let params = self.bindings(bindings)?;
+                    let target = LoopTarget {
+                        start: self.ops.len(),
+                        scope,
+                        params,
+                    };
+                    let outer = self.target.replace(target);
+                    self.expr(body, tail);
+                    self.target = outer;
+                    self.emit(Op::PopScope);
+                    return Ok(());
+                }
+                "recur" => {
+                    let (start, scope, params) = match &self.target {
+                        Some(t) => (t.start, t.scope, t.params.clone()),
+                        None => return Err(RecurOutsideLoop),
+                    };
+                    let argc = l.len() - 1;
+                    self.emit(Op::Allocate(argc));
+                    rest.for_each(|arg| self.expr(arg, false));
+                    self.emit(Op::Recur {
+                        argc,
+                        start,
+                        scope,
+                        params,
+                    });
+                    return Ok(());
+                }
+                "do" => {
+                    if l.len() == 1 {
+                        self.expr(&MalType::Nil, tail);
+                    }
+                    for (i, x) in rest.enumerate() {
+                        if i + 2 < l.len() {
+                            self.expr(x, false);
+                            self.emit(Op::Pop);
+                        } else {
+                            self.expr(x, tail);
+                        }
+                    }
+                    return Ok(());
+                }
+                "if" => {
+                    self.expr(rest.next().ok_or(WrongArgAmount)?, false);
+                    let then = match rest.next() {
+                        Some(then) => then,
+                        None => {
+                            self.emit(Op::Pop);
+                            return Err(WrongArgAmount);
+                        }
+                    };
+                    let otherwise = self.emit(Op::JumpIfFalse(0));
+                    self.expr(then, tail);
+                    let end = self.emit(Op::Jump(0));
+                    self.patch(otherwise);
+                    self.expr(rest.next().unwrap_or(&MalType::Nil), tail);
+                    self.patch(end);
+                    return Ok(());
+                }
+                "fn*" => {
+                    let mut first = rest.next().ok_or(WrongArgAmount)?;
+                    let name = match first {
+                        MalType::Symbol(name) => {
+                            first = rest.next().ok_or(WrongArgAmount)?;
+                            Some(name.clone())
+                        }
+                        _ => None,
+                    };
+                    let params = first.clone().to_list()?;
+                    for param in params.iter() {
+                        param.to_symbol()?;
+                    }
+                    let body = rest.next().ok_or(WrongArgAmount)?.clone();
+                    let frames = self.frames.nested(name.clone(), &params, &body);
+                    let chunk = Rc::new(Compiler::new(frames).finish(&body)?);
+                    self.emit(Op::Closure(Rc::new(Proto {
+                        name,
+                        params,
+                        body,
+                        chunk,
+                    })));
+                    return Ok(());
+                }
+                "quote" => {
+                    self.emit(Op::Const(rest.next().ok_or(WrongArgAmount)?.clone()));
+                    return Ok(());
+                }
+                _ if runs_on_tree(form) => {
+                    self.emit(Op::Tree(form.clone()));
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+        self.emit(Op::Allocate(l.len()));
+        l.iter().for_each(|x| self.expr(x, false));
+        let argc = l.len() - 1;
+        let head = match l.head() {
+            Some(MalType::Symbol(s)) => Some(s.clone()),
+            _ => None,
+        };
+        self.emit(if tail {
+            Op::TailCall(argc, head)
+        } else {
+            Op::Call(argc, head)
+        });
+        Ok(())
+    }
+
+    /// Compiles `let*` or `loop` bindings into the current frame and returns their names.
+    fn bindings(&mut self, bindings: Vector<MalType>) -> MidResult<Vector<MalType>> {
+        let mut names = Vector::new();
+        let mut bindings = bindings.into_iter();
+        while let Some(name) = bindings.next() {
+            let name = name.to_symbol()?;
+            self.expr(&bindings.next().ok_or(WrongArgAmount)?, false);
+            self.emit(Op::Bind(name.clone()));
+            self.frames.bind(&name);
+            names.push_back(MalType::Symbol(name));
+        }
+        self.frames.close();
+        Ok(names)
+    }
+}
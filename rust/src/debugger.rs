@@ -0,0 +1,162 @@
+use env::Env;
+use eval::rep;
+use sandbox;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::io::{self, BufRead, Write};
+use types::{EvalError, MalType, MidResult};
+
+const HELP: &str = ":s, :step       evaluate until the next form
+:n, :next       step over the current form
+:o, :out        run until the current function or form returns
+:c, :continue   run until the next breakpoint
+:f, :form       print the current form
+:l, :locals     print the local bindings, innermost frame first
+:q, :quit       abort the evaluation
+<expr>          evaluate expr in the paused frame";
+
+/// What the debugger does when evaluation reaches the next form.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Run,
+    StepInto,
+    /// Pause at the next form at this eval depth or above.
+    StepOver(usize),
+    /// Pause at the next form above this eval depth.
+    StepOut(usize),
+}
+
+thread_local! {
+    static MODE: Cell<Mode> = const { Cell::new(Mode::Run) };
+    static PAUSED: Cell<bool> = const { Cell::new(false) };
+    static BREAKPOINTS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+/// Pauses whenever the function named `name` is called, as `(break-fn 'name)` does.
+pub fn set_breakpoint(name: &str) {
+    BREAKPOINTS.with(|b| b.borrow_mut().insert(name.to_string()));
+}
+
+pub fn clear_breakpoint(name: &str) {
+    BREAKPOINTS.with(|b| b.borrow_mut().remove(name));
+}
+
+pub fn has_breakpoint(name: &str) -> bool {
+    BREAKPOINTS.with(|b| b.borrow().contains(name))
+}
+
+/// Whether stepping or a breakpoint may pause evaluation.
+pub fn active() -> bool {
+    MODE.with(|m| m.get()) != Mode::Run || BREAKPOINTS.with(|b| !b.borrow().is_empty())
+}
+
+/// Stops stepping, e.g. once a top-level REPL form has finished.
+pub fn resume() {
+    MODE.with(|m| m.set(Mode::Run));
+}
+
+/// Called by eval before each list form at eval depth `depth`; pauses if stepping says so.
+pub fn before(form: &MalType, env: &Env, depth: usize) -> MidResult<()> {
+    let pause_here = match MODE.with(|m| m.get()) {
+        Mode::Run => false,
+        Mode::StepInto => true,
+        Mode::StepOver(over) => depth <= over,
+        Mode::StepOut(out) => depth < out,
+    };
+    if pause_here {
+        pause(form, env, depth)
+    } else {
+        Ok(())
+    }
+}
+
+/// Pauses at the body of `name` when it has a breakpoint.
+pub fn on_call(name: &str, body: &MalType, env: &Env, depth: usize) -> MidResult<()> {
+    if sandbox::active() {
+        return Ok(());
+    }
+    eprintln!("breakpoint in {}", name);
+    match body {
+        // eval is about to reach the body and will pause there
+        MalType::List(_) => {
+            MODE.with(|m| m.set(Mode::StepInto));
+            Ok(())
+        }
+        _ => pause(body, env, depth),
+    }
+}
+
+/// Stops at `form` and reads debugger commands from stdin until one resumes evaluation.
+/// Forms evaluated at the prompt run without stepping or breakpoints. Sandboxed code never
+/// pauses, so it cannot wait on the host's stdin.
+pub fn pause(form: &MalType, env: &Env, depth: usize) -> MidResult<()> {
+    if sandbox::active() || PAUSED.with(|p| p.replace(true)) {
+        return Ok(());
+    }
+    MODE.with(|m| m.set(Mode::Run));
+    eprintln!("paused at {}", form);
+    let result = prompt(form, env, depth);
+    PAUSED.with(|p| p.set(false));
+    result
+}
+
+fn prompt(form: &MalType, env: &Env, depth: usize) -> MidResult<()> {
+    let stdin = io::stdin();
+    loop {
+        eprint!("debug> ");
+        let _ = io::stderr().flush();
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            // with nobody to ask, carry on as if continued
+            Ok(0) | Err(_) => return Ok(()),
+            Ok(_) => {}
+        }
+        let mode = match line.trim() {
+            "" => continue,
+            ":s" | ":step" => Mode::StepInto,
+            ":n" | ":next" => Mode::StepOver(depth),
+            ":o" | ":out" => Mode::StepOut(depth),
+            ":c" | ":continue" => Mode::Run,
+            ":q" | ":quit" => return Err(EvalError::Interrupted),
+            ":f" | ":form" => {
+                eprintln!("{}", form);
+                continue;
+            }
+            ":l" | ":locals" => {
+                print_locals(env);
+                continue;
+            }
+            ":h" | ":help" => {
+                eprintln!("{}", HELP);
+                continue;
+            }
+            expr => {
+                match rep(expr.to_string(), env) {
+                    Ok(result) => eprintln!("{}", result),
+                    Err(e) => eprintln!("{}", e),
+                }
+                continue;
+            }
+        };
+        MODE.with(|m| m.set(mode));
+        return Ok(());
+    }
+}
+
+fn print_locals(env: &Env) {
+    let frames = env.locals();
+    if frames.is_empty() {
+        eprintln!("no locals");
+    }
+    for (i, mut frame) in frames.into_iter().enumerate() {
+        if frame.is_empty() {
+            continue;
+        }
+        frame.sort_by(|a, b| a.0.cmp(&b.0));
+        let bindings = frame
+            .into_iter()
+            .map(|(name, value)| format!("{} {}", name, value))
+            .collect::<Vec<String>>();
+        eprintln!("{}: {}", i, bindings.join(", "));
+    }
+}
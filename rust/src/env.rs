@@ -1,15 +1,30 @@
 use im_rc::Vector;
-use std::cell::RefCell;
+use std::cell::{OnceCell, RefCell};
 use std::collections::HashMap;
 use std::ops::Deref;
 use std::rc::Rc;
+use gc;
+use eval::Settings;
+use sandbox::Budget;
 use types::EvalError;
 use types::EvalError::WrongArgAmount;
+use types::Sym;
+use symbol::SymKey;
 use MalType;
 
+/// The bindings of one environment. The outermost environment holds every global
+/// definition and is a map; the frames of functions, `let*` and `loop` hold a handful of
+/// names and are searched in order, which lets the compiler address them by slot.
+enum Frame {
+    Global(HashMap<Sym, MalType>),
+    Local(Vec<(Sym, MalType)>),
+}
+
 pub struct InnerEnv {
-    data: RefCell<HashMap<String, MalType>>,
+    data: RefCell<Frame>,
     outer: Option<Env>,
+    /// What an outermost environment built by `EnvBuilder` was built with.
+    settings: OnceCell<Rc<Settings>>,
 }
 
 #[derive(Clone)]
@@ -23,73 +38,190 @@ impl Deref for Env {
     }
 }
 
+impl Default for Env {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frame {
+    fn get(&self, key: &Sym) -> Option<&MalType> {
+        match self {
+            Frame::Global(map) => map.get(key),
+            Frame::Local(slots) => slots.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+        }
+    }
+}
+
+impl InnerEnv {
+    /// The environment this one extends, if it is not the outermost.
+    pub(crate) fn outer(&self) -> Option<&Env> {
+        self.outer.as_ref()
+    }
+    /// Calls `f` with each value bound in this frame, for the cycle collector. False if
+    /// the frame is being modified.
+    pub(crate) fn try_for_each_value<F: FnMut(&MalType)>(&self, mut f: F) -> bool {
+        match self.data.try_borrow() {
+            Ok(data) => {
+                match &*data {
+                    Frame::Global(map) => map.values().for_each(f),
+                    Frame::Local(slots) => slots.iter().for_each(|(_, v)| f(v)),
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+    /// Unbinds everything in this frame, once the cycle collector finds it unreachable.
+    pub(crate) fn clear(&self) {
+        let empty = match &*self.data.borrow() {
+            Frame::Global(_) => Frame::Global(HashMap::new()),
+            Frame::Local(_) => Frame::Local(Vec::new()),
+        };
+        // the old bindings are dropped after the frame is released
+        let _old = self.data.replace(empty);
+    }
+}
+
 impl Env {
     pub fn new() -> Self {
-        Env(Rc::new(InnerEnv {
-            data: RefCell::new(HashMap::new()),
+        Env::track(InnerEnv {
+            data: RefCell::new(Frame::Global(HashMap::new())),
             outer: None,
-        }))
-    }
-    pub fn set(&self, key: String, value: MalType) {
-        self.data.borrow_mut().insert(key, value);
-    }
-    pub fn find(&self, key: &String) -> Option<Env> {
-        if self.data.borrow().contains_key(key.as_str()) {
-            Some(self.clone())
-        } else {
-            match &self.outer {
-                Some(outer) => outer.find(key),
-                None => None,
+            settings: OnceCell::new(),
+        })
+    }
+    /// Evaluates everything in this environment and the ones extending it with `settings`
+    /// from now on. False if it already has settings or is not outermost.
+    pub fn set_settings(&self, settings: Settings) -> bool {
+        self.outer.is_none() && self.settings.set(Rc::new(settings)).is_ok()
+    }
+    /// The settings of the outermost environment, if it was given any.
+    pub fn settings(&self) -> Option<Rc<Settings>> {
+        let mut env = self;
+        while let Some(outer) = &env.outer {
+            env = outer;
+        }
+        env.settings.get().cloned()
+    }
+    /// The sandbox budget of the outermost environment, if it has one.
+    pub fn budget(&self) -> Option<Rc<Budget>> {
+        self.settings().and_then(|s| s.budget.clone())
+    }
+    fn track(inner: InnerEnv) -> Self {
+        let env = Rc::new(inner);
+        gc::track_env(&env);
+        Env(env)
+    }
+    /// Binds `key` in this environment. A name already bound here keeps its slot.
+    pub fn set<K: Into<Sym>>(&self, key: K, value: MalType) {
+        let key = key.into();
+        match &mut *self.data.borrow_mut() {
+            Frame::Global(map) => {
+                map.insert(key, value);
+            }
+            Frame::Local(slots) => match slots.iter_mut().find(|(k, _)| *k == key) {
+                Some(slot) => slot.1 = value,
+                None => slots.push((key, value)),
+            },
+        }
+    }
+    pub fn find<K: SymKey>(&self, key: K) -> Option<Env> {
+        let key = key.sym()?;
+        let mut env = self;
+        loop {
+            if env.data.borrow().get(&key).is_some() {
+                return Some(env.clone());
             }
+            env = env.outer.as_ref()?;
         }
     }
-    pub fn get(&self, key: &String) -> Option<MalType> {
-        match self.find(key) {
-            Some(env) => env.data.borrow().get(key.as_str()).cloned(),
-            None => None,
+    /// Every name bound in this environment or an outer one.
+    pub fn symbols(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.bindings().into_iter().map(|(k, _)| k).collect();
+        if let Some(outer) = &self.outer {
+            names.extend(outer.symbols());
+        }
+        names
+    }
+    /// The names and values bound directly in this environment, not in outer ones.
+    pub fn bindings(&self) -> Vec<(String, MalType)> {
+        match &*self.data.borrow() {
+            Frame::Global(map) => map.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
+            Frame::Local(slots) => slots.iter().map(|(k, v)| (k.to_string(), v.clone())).collect(),
+        }
+    }
+    /// The bindings of every frame except the outermost (global) one, innermost first.
+    pub fn locals(&self) -> Vec<Vec<(String, MalType)>> {
+        let mut frames = Vec::new();
+        let mut env = self;
+        while let Some(outer) = &env.outer {
+            frames.push(env.bindings());
+            env = outer;
+        }
+        frames
+    }
+    pub fn get<K: SymKey>(&self, key: K) -> Option<MalType> {
+        let key = key.sym()?;
+        let mut env = self;
+        loop {
+            if let Some(value) = env.data.borrow().get(&key) {
+                return Some(value.clone());
+            }
+            env = env.outer.as_ref()?;
+        }
+    }
+    /// The value in slot `slot` of the frame `depth` levels out, as resolved by the
+    /// compiler, if that slot holds `key`.
+    pub fn get_slot(&self, depth: usize, slot: usize, key: &Sym) -> Option<MalType> {
+        let mut env = self;
+        for _ in 0..depth {
+            env = env.outer.as_ref()?;
+        }
+        match &*env.data.borrow() {
+            Frame::Local(slots) => match slots.get(slot) {
+                Some((k, v)) if k == key => Some(v.clone()),
+                _ => None,
+            },
+            Frame::Global(_) => None,
         }
     }
 
     pub fn new_env(&self) -> Self {
-        Env {
-            0: Rc::new(InnerEnv {
-                data: RefCell::new(HashMap::new()),
-                outer: Some(self.clone()),
-            }),
-        }
+        Env::track(InnerEnv {
+            data: RefCell::new(Frame::Local(Vec::new())),
+            outer: Some(self.clone()),
+            settings: OnceCell::new(),
+        })
     }
     pub fn bind2(&self, symbols: MalType, values: MalType) -> Result<(), EvalError> {
         let symbols = symbols.to_list()?;
         let values = values.to_list()?;
-        self.bind(symbols, values)
+        self.bind(&symbols, values)
     }
 
     pub fn bind(
         &self,
-        symbols: Vector<MalType>,
+        symbols: &Vector<MalType>,
         mut values: Vector<MalType>,
     ) -> Result<(), EvalError> {
-        let mut symbols = symbols.into_iter();
-        loop {
-            if let Some(symbol) = symbols.next() {
-                let symbol = symbol.to_symbol()?;
-                if symbol == "&" {
-                    let symbol = symbols.next().ok_or(WrongArgAmount)?.to_symbol()?;
-                    self.set(symbol, MalType::List(values));
-                    break;
-                } else {
-                    let value = values.pop_front().ok_or(WrongArgAmount)?.clone();
-                    self.set(symbol, value);
-                }
-            } else {
+        let mut symbols = symbols.iter();
+        while let Some(symbol) = symbols.next() {
+            let symbol = symbol.to_symbol()?;
+            if &*symbol == "&" {
+                let symbol = symbols.next().ok_or(WrongArgAmount)?.to_symbol()?;
+                self.set(symbol, MalType::List(values));
                 break;
+            } else {
+                let value = values.pop_front().ok_or(WrongArgAmount)?;
+                self.set(symbol, value);
             }
         }
         Ok(())
     }
     pub fn new_bind(
         &self,
-        symbols: Vector<MalType>,
+        symbols: &Vector<MalType>,
         values: Vector<MalType>,
     ) -> Result<Self, EvalError> {
         let new_env = self.new_env();
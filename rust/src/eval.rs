@@ -0,0 +1,602 @@
+use analyzer::{analyze, Expr, Form, Lambda};
+use debugger;
+use env::Env;
+use functions::{default_env_non_native, natives, Functions, InnerFunction, SimpleFn};
+use gc;
+use im_rc::{HashMap, Vector};
+use interrupt;
+use logger;
+use loops::Loop;
+use profiler;
+use reader::{read_str, Reader};
+use sandbox;
+use sandbox::{Budget, Sandbox};
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::iter;
+use std::str::FromStr;
+use std::rc::Rc;
+use std::thread;
+use std::time::Duration;
+use types::EvalError::{RecurOutsideLoop, StackOverflow, SymbolNotFound, WrongArgAmount};
+use types::MalType::{List, Nil};
+use types::{EvalError, EvalResult, MalType, MidResult};
+use vm;
+
+/// The forms `eval` handles itself rather than by calling a function.
+pub const SPECIAL_FORMS: &[&str] = &["def!", "let*", "loop", "recur", "do", "if", "fn*", "quote", "break!", "profile"];
+
+/// How deeply `eval` may nest before giving up with `EvalError::StackOverflow`. Small
+/// enough for the 8 MB stack of a main thread, even in a debug build; raise it with
+/// `EnvBuilder::max_depth` when evaluating on a larger stack.
+pub const DEFAULT_MAX_DEPTH: usize = 400;
+/// Stack size for the thread `with_eval_stack` runs on.
+pub const EVAL_STACK_SIZE: usize = 512 * 1024 * 1024;
+/// The depth limit for environments evaluated on an `EVAL_STACK_SIZE` stack.
+pub const EVAL_STACK_MAX_DEPTH: usize = 10_000;
+
+/// The environment variable the binaries read the engine from, `tree` or `vm`.
+pub const ENGINE_VAR: &str = "MAL_ENGINE";
+
+/// How `eval` runs forms.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Engine {
+    /// Walk the forms directly. The tracer, debugger and profiler hook in here.
+    #[default]
+    Tree,
+    /// Compile forms to bytecode and run them on the stack machine in `vm`.
+    Vm,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Engine, String> {
+        match s {
+            "tree" => Ok(Engine::Tree),
+            "vm" => Ok(Engine::Vm),
+            _ => Err(format!("unknown engine: {}", s)),
+        }
+    }
+}
+
+impl Engine {
+    /// The engine named by `MAL_ENGINE`, if it is set.
+    pub fn from_env() -> Result<Option<Engine>, String> {
+        match env::var(ENGINE_VAR) {
+            Ok(name) => name.parse().map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// How code is evaluated in an environment built by `EnvBuilder` and the environments
+/// extending it.
+pub struct Settings {
+    pub max_depth: usize,
+    pub engine: Engine,
+    /// What is left of the sandbox's budgets, if the environment has a sandbox.
+    pub budget: Option<Rc<Budget>>,
+}
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    /// The settings of the environment being evaluated in.
+    static ACTIVE: RefCell<Option<Rc<Settings>>> = const { RefCell::new(None) };
+}
+
+/// Keeps the settings of an environment in force while evaluating in it, and restores
+/// the ones in force before when dropped.
+pub(crate) struct Scope {
+    previous: Option<Rc<Settings>>,
+    _sandbox: sandbox::Scope,
+}
+
+impl Scope {
+    pub(crate) fn enter(env: &Env) -> Scope {
+        let settings = env.settings();
+        let budget = settings.as_ref().and_then(|s| s.budget.clone());
+        Scope {
+            previous: ACTIVE.with(|a| a.replace(settings)),
+            _sandbox: sandbox::Scope::enter(budget),
+        }
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        ACTIVE.with(|a| *a.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Whether evaluating in `env` follows the settings in force now.
+pub(crate) fn is_current(env: &Env) -> bool {
+    let settings = env.settings();
+    ACTIVE.with(|a| match (a.borrow().as_ref(), &settings) {
+        (Some(active), Some(settings)) => Rc::ptr_eq(active, settings),
+        (None, None) => true,
+        _ => false,
+    })
+}
+
+/// The engine of the environment being evaluated in.
+pub fn engine() -> Engine {
+    ACTIVE.with(|a| a.borrow().as_ref().map_or(Engine::Tree, |s| s.engine))
+}
+
+/// Whether the tracer, debugger or profiler is on, which only the tree walker reports
+/// to. The VM hands evaluation to it meanwhile.
+pub fn hooked() -> bool {
+    logger::tracing() || logger::tracing_functions() || debugger::active() || profiler::active()
+}
+
+/// The engine to evaluate with now: the one set, unless it is the VM and `hooked()`.
+fn running_engine() -> Engine {
+    match engine() {
+        Engine::Vm if hooked() => Engine::Tree,
+        engine => engine,
+    }
+}
+
+/// The eval depth limit of the environment being evaluated in.
+pub fn max_depth() -> usize {
+    ACTIVE.with(|a| a.borrow().as_ref().map_or(DEFAULT_MAX_DEPTH, |s| s.max_depth))
+}
+
+/// Counts one level of evaluation against the eval depth limit while it is alive.
+pub(crate) struct DepthGuard;
+
+impl DepthGuard {
+    pub(crate) fn enter() -> MidResult<DepthGuard> {
+        DEPTH.with(|d| {
+            let depth = d.get();
+            if depth >= max_depth() {
+                return Err(StackOverflow(depth));
+            }
+            d.set(depth + 1);
+            Ok(DepthGuard)
+        })
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|d| d.set(d.get() - 1));
+    }
+}
+
+/// Runs a collection if one is due and nothing is being evaluated, so that no value the
+/// collector can't see is in use. Hosts call it between the forms they evaluate.
+pub fn safe_point() {
+    if DEPTH.with(|d| d.get()) == 0 {
+        gc::maybe_collect();
+    }
+}
+
+/// Runs `f` on a thread with an `EVAL_STACK_SIZE` stack, so environments built with
+/// `EVAL_STACK_MAX_DEPTH` hit the depth limit before the native stack runs out.
+pub fn with_eval_stack<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    thread::Builder::new()
+        .stack_size(EVAL_STACK_SIZE)
+        .spawn(f)
+        .expect("failed to spawn eval thread")
+        .join()
+        .expect("eval thread panicked")
+}
+
+/// How many evals enclose the current one, used to indent traces.
+fn depth() -> usize {
+    DEPTH.with(|d| d.get().saturating_sub(1))
+}
+
+/// Calls `f` with already evaluated `args`.
+pub fn apply(f: MalType, args: Vector<MalType>) -> EvalResult {
+    let _frame = profile_call(None, &f);
+    match f {
+        MalType::Function(f) => f.call(args),
+        MalType::NonNativeFunction(f) => call_closure(&f, args),
+        other => Err(EvalError::InvalidType("function".to_string(), other.type_string())),
+    }
+}
+
+/// Calls the mal function `f` with the settings of the environment it was created in.
+fn call_closure(f: &InnerFunction, args: Vector<MalType>) -> EvalResult {
+    let _scope = Scope::enter(&f.env);
+    let env = f.env.new_bind(&f.params, args)?;
+    if let Some(name) = f.name.as_ref().filter(|n| debugger::has_breakpoint(n)) {
+        debugger::on_call(name, &f.ast, &env, depth())?;
+    }
+    match running_engine() {
+        Engine::Tree => walk(f.expr()?, env),
+        Engine::Vm => vm::call(f, env),
+    }
+}
+
+/// The name a call to `f` is known by for `wanted`, which is checked against the symbol
+/// it was called by and the name it was defined with, e.g. to find traced functions.
+fn called_as(head: Option<&str>, f: &MalType, wanted: fn(&str) -> bool) -> Option<String> {
+    if let Some(head) = head.filter(|h| wanted(h)) {
+        return Some(head.to_string());
+    }
+    match f {
+        MalType::NonNativeFunction(f) => f.name.as_ref().filter(|n| wanted(n)).map(|n| n.to_string()),
+        _ => None,
+    }
+}
+
+/// Starts timing a call to `f` when the profiler is on. Functions are profiled under the
+/// name they were defined with, or else the symbol they were called by.
+fn profile_call(head: Option<&str>, f: &MalType) -> Option<profiler::Frame> {
+    if !profiler::active() {
+        return None;
+    }
+    let name = match f {
+        MalType::NonNativeFunction(f) => f.name.as_deref().or(head).unwrap_or("<anonymous>"),
+        _ => head.unwrap_or("<native>"),
+    };
+    profiler::enter(name)
+}
+
+/// Calls `f` as the tree walker does when the symbol `head` called it, reporting the call
+/// to the tracer, debugger and profiler.
+pub fn call_hooked(head: Option<&str>, f: MalType, args: Vector<MalType>) -> EvalResult {
+    if let Some(name) = called_as(head, &f, logger::is_traced) {
+        return call_traced(&name, f, args);
+    }
+    let breakpoint = called_as(head, &f, debugger::has_breakpoint);
+    let _frame = profile_call(head, &f);
+    match f {
+        MalType::Function(f) => f.call(args),
+        MalType::NonNativeFunction(f) => {
+            let _scope = Scope::enter(&f.env);
+            let env = f.env.new_bind(&f.params, args)?;
+            if let Some(name) = breakpoint {
+                debugger::on_call(&name, &f.ast, &env, depth())?;
+            }
+            walk(f.expr()?, env)
+        }
+        other => Err(EvalError::InvalidType("Function".to_string(), other.type_string())),
+    }
+}
+
+/// Calls a traced function outside the tail-call loop, so its result can be printed.
+fn call_traced(name: &str, f: MalType, args: Vector<MalType>) -> EvalResult {
+    let mut call = args.clone();
+    call.push_front(MalType::Symbol(name.into()));
+    logger::trace_call(format_args!("{}", List(call)));
+    let result = apply(f, args);
+    match &result {
+        Ok(value) => logger::trace_return(format_args!("{}", value)),
+        Err(e) => logger::trace_return(format_args!("error: {}", e)),
+    }
+    result
+}
+
+pub fn eval(ast: &MalType, env: &Env) -> EvalResult {
+    let _scope = Scope::enter(env);
+    match running_engine() {
+        Engine::Tree => walk(analyze(ast)?, env.clone()),
+        Engine::Vm => vm::eval(ast, env),
+    }
+}
+
+/// Evaluates `ast` on the tree walker whatever the engine, for the forms the compiler
+/// leaves to it.
+pub(crate) fn walk_form(ast: &MalType, env: &Env) -> EvalResult {
+    walk(analyze(ast)?, env.clone())
+}
+
+fn walk(mut expr: Rc<Expr>, mut env: Env) -> EvalResult {
+    let _depth = DepthGuard::enter()?;
+    let mut current_loop: Option<Rc<Loop<Rc<Expr>>>> = None;
+    // the profiled function this eval is running, replaced on each tail call
+    let mut frame: Option<profiler::Frame> = None;
+    loop {
+        sandbox::consume_fuel()?;
+        interrupt::check()?;
+        let (list, form) = match &*expr {
+            Expr::List(list, form) => (list, form),
+            _ => return eval_expr(&expr, &env),
+        };
+        if logger::tracing() {
+            logger::trace(depth(), format_args!("{}", list));
+        }
+        debugger::before(list, &env, depth())?;
+        let next = match form {
+            Form::Def(key, value) => {
+                let value = walk(value.clone(), env.clone())?;
+                if logger::tracing() {
+                    logger::trace(depth(), format_args!("def! {} {}", key, value));
+                }
+                env.set(key, value.clone());
+                return Ok(value);
+            }
+            Form::Let(bindings, body) => {
+                env = env.new_env();
+                for (key, value) in bindings {
+                    let value = walk(value.clone(), env.clone())?;
+                    env.set(key, value);
+                }
+                body.clone()
+            }
+            Form::Loop(bindings, body) => {
+                let outer = env.clone();
+                env = env.new_env();
+                let mut params = Vector::new();
+                for (key, value) in bindings {
+                    let value = walk(value.clone(), env.clone())?;
+                    env.set(key, value);
+                    params.push_back(MalType::Symbol(key.clone()));
+                }
+                current_loop = Some(Rc::new(Loop {
+                    params,
+                    body: body.clone(),
+                    env: outer,
+                }));
+                body.clone()
+            }
+            Form::Recur(args) => {
+                let target = current_loop.clone().ok_or(RecurOutsideLoop)?;
+                let (new_env, body) = target.recur(eval_all(args, &env)?)?;
+                env = new_env;
+                body
+            }
+            Form::Do(body, last) => {
+                for x in body {
+                    walk(x.clone(), env.clone())?;
+                }
+                last.clone()
+            }
+            Form::If(cond, then, otherwise) => {
+                if walk(cond.clone(), env.clone())?.to_bool() {
+                    then.clone()
+                } else {
+                    otherwise.clone()
+                }
+            }
+            Form::Fn(lambda) => return Ok(closure(lambda, &env)),
+            Form::Quote(value) => return Ok(value.clone()),
+            Form::Break => {
+                debugger::pause(list, &env, depth())?;
+                return Ok(Nil);
+            }
+            Form::Profile(inner) => {
+                if profiler::active() {
+                    inner.clone()
+                } else {
+                    let (result, profile) = profiler::profile(|| walk(inner.clone(), env.clone()));
+                    eprintln!("{}", profile.report());
+                    return result;
+                }
+            }
+            Form::Call(head, items) => {
+                let head = head.as_ref().map(|h| h.as_str());
+                let mut args = eval_all(items, &env)?;
+                let f = args.pop_front().unwrap();
+                let breakpoint = called_as(head, &f, debugger::has_breakpoint);
+                if let Some(name) = called_as(head, &f, logger::is_traced) {
+                    return call_traced(&name, f, args);
+                }
+                // a tail call to a mal function ends the profiled call this eval was running
+                if let MalType::NonNativeFunction(_) = f {
+                    drop(frame.take());
+                }
+                let call_frame = profile_call(head, &f);
+                match f {
+                    MalType::Function(f) => {
+                        let _frame = call_frame;
+                        return f.call(args);
+                    }
+                    // a function from an environment with other settings runs with them
+                    MalType::NonNativeFunction(f) if !is_current(&f.env) => {
+                        let _frame = call_frame;
+                        return call_closure(&f, args);
+                    }
+                    MalType::NonNativeFunction(f) => {
+                        env = f.env.new_bind(&f.params, args)?;
+                        current_loop = None;
+                        frame = call_frame;
+                        if let Some(name) = breakpoint {
+                            debugger::on_call(&name, &f.ast, &env, depth())?;
+                        }
+                        f.expr()?
+                    }
+                    other => return Err(EvalError::InvalidType("Function".to_string(), other.type_string())),
+                }
+            }
+        };
+        expr = next;
+    }
+}
+
+/// Creates the closure a `fn*` form evaluates to, sharing its analysed body.
+fn closure(lambda: &Lambda, env: &Env) -> MalType {
+    let closure = InnerFunction::closure(lambda.name.clone(), lambda.params.clone(), lambda.body.clone(), env);
+    if let MalType::NonNativeFunction(f) = &closure {
+        let _ = f.expr.set(lambda.expr.clone());
+    }
+    closure
+}
+
+/// Evaluates each of `exprs` in turn.
+fn eval_all(exprs: &[Rc<Expr>], env: &Env) -> MidResult<Vector<MalType>> {
+    sandbox::allocate(exprs.len())?;
+    exprs.iter().map(|x| walk(x.clone(), env.clone())).collect()
+}
+
+/// Evaluates an expression that is not a list, which never needs the tail-call loop.
+fn eval_expr(expr: &Rc<Expr>, env: &Env) -> EvalResult {
+    match &**expr {
+        Expr::Const(value) => Ok(value.clone()),
+        Expr::Symbol(s) => env.get(s).ok_or_else(|| SymbolNotFound(s.to_string())),
+        Expr::Local { depth, slot, name } => env
+            .get_slot(*depth, *slot, name)
+            .or_else(|| env.get(name))
+            .ok_or_else(|| SymbolNotFound(name.to_string())),
+        Expr::Vector(items) => Ok(MalType::Vector(eval_all(items, env)?)),
+        Expr::HashMap(entries) => {
+            sandbox::allocate(entries.len() * 2)?;
+            let mut map = HashMap::new();
+            for (key, value) in entries {
+                let k = walk(key.clone(), env.clone())?;
+                if !k.is_hashable() {
+                    return Err(EvalError::InvalidHashKey(k));
+                }
+                let value = walk(value.clone(), env.clone())?;
+                map.insert(k, value);
+            }
+            Ok(MalType::HashMap(map))
+        }
+        Expr::List(..) => walk(expr.clone(), env.clone()),
+    }
+}
+
+/// Evaluates `ast`, failing with `EvalError::Timeout` if it runs longer than `timeout`.
+pub fn eval_with_timeout(ast: &MalType, env: &Env, timeout: Duration) -> EvalResult {
+    interrupt::with_timeout(timeout, || eval(ast, env))
+}
+
+pub fn rep(text: String, env: &Env) -> Result<String, EvalError> {
+    Ok(eval(&read_str(text)?, env)?.pr_str(true))
+}
+
+/// The default environment plus `eval`, `swap!` and the functions defined in mal itself.
+pub fn repl_env() -> Env {
+    EnvBuilder::new().build()
+}
+
+/// Binds `*ARGV*` to the command-line arguments following the script name.
+pub fn set_argv(env: &Env, args: Vec<String>) {
+    let args = args.into_iter().map(MalType::from).collect::<Vector<MalType>>();
+    env.set("*ARGV*".to_string(), List(args));
+}
+
+/// Evaluates the forms in `source` in order, reading each only once the one before it
+/// has run, and returns nil.
+pub fn load<R: Read>(source: R, env: &Env) -> EvalResult {
+    for form in Reader::new(source) {
+        eval(&form?, env)?;
+        safe_point();
+    }
+    Ok(Nil)
+}
+
+/// Evaluates every form in the file at `path`, as the `load-file` native does.
+pub fn load_file(path: &str, env: &Env) -> EvalResult {
+    let _scope = Scope::enter(env);
+    sandbox::check_path(path)?;
+    let file = File::open(path).map_err(|_| EvalError::InvalidFile(path.to_string()))?;
+    load(file, env)
+}
+
+/// Runs a script the way `mal script.mal args...` does: binds `*ARGV*` and loads the file,
+/// or standard input if `path` is `-`.
+pub fn run_script(path: &str, args: Vec<String>, env: &Env) -> EvalResult {
+    set_argv(env, args);
+    match path {
+        "-" => load(io::stdin().lock(), env),
+        _ => load_file(path, env),
+    }
+}
+
+/// Configures and builds a top-level environment.
+#[derive(Clone, Default)]
+pub struct EnvBuilder {
+    max_depth: Option<usize>,
+    engine: Engine,
+    sandbox: Option<Sandbox>,
+}
+
+impl EnvBuilder {
+    pub fn new() -> Self {
+        EnvBuilder::default()
+    }
+
+    /// A builder for a fresh environment set up the way `env` was.
+    pub fn like(env: &Env) -> Self {
+        match env.settings() {
+            Some(settings) => EnvBuilder {
+                max_depth: Some(settings.max_depth),
+                engine: settings.engine,
+                sandbox: settings.budget.as_ref().map(|b| b.sandbox().clone()),
+            },
+            None => EnvBuilder::new(),
+        }
+    }
+
+    /// Overrides `DEFAULT_MAX_DEPTH` for code evaluated in the environment.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// The engine code evaluated in the environment runs on, `Engine::Tree` by default.
+    pub fn engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// Restricts the natives, files and budgets available to code evaluated in the
+    /// environment.
+    pub fn sandbox(mut self, sandbox: Sandbox) -> Self {
+        self.sandbox = Some(sandbox);
+        self
+    }
+
+    pub fn build(self) -> Env {
+        let allowed = |name: &str| match &self.sandbox {
+            Some(sandbox) => sandbox.allows_native(name),
+            None => true,
+        };
+        let env = Env::new();
+        natives()
+            .into_iter()
+            .chain(iter::once(("swap!", swap as SimpleFn)))
+            .filter(|(name, _)| allowed(name))
+            .for_each(|(name, f)| env.set(name.to_string(), MalType::Function(Functions::Native(f))));
+        if allowed("eval") {
+            let env_cloned = env.clone();
+            let eval_mal_func = move |mut args: Vector<MalType>| {
+                let first = args.pop_front().ok_or(WrongArgAmount)?;
+                eval(&first, &env_cloned)
+            };
+            let eval_mal_func = MalType::Function(Functions::NonNative(Rc::new(eval_mal_func)));
+            env.set("eval".to_string(), eval_mal_func);
+        }
+        if allowed("load-file") {
+            let env_cloned = env.clone();
+            let load_file_func = move |mut args: Vector<MalType>| {
+                let path = args.pop_front().ok_or(WrongArgAmount)?.to_mal_string()?;
+                load_file(&path.into_iter().collect::<String>(), &env_cloned)
+            };
+            let load_file_func = MalType::Function(Functions::NonNative(Rc::new(load_file_func)));
+            env.set("load-file".to_string(), load_file_func);
+        }
+        set_argv(&env, Vec::new());
+        default_env_non_native().into_iter().for_each(|s| {
+            rep(s, &env).expect("default environment should evaluate");
+        });
+        env.set_settings(Settings {
+            max_depth: self.max_depth.unwrap_or(DEFAULT_MAX_DEPTH),
+            engine: self.engine,
+            budget: self.sandbox.map(|sandbox| Rc::new(Budget::new(sandbox))),
+        });
+        env
+    }
+}
+
+fn swap(mut args: Vector<MalType>) -> EvalResult {
+    let first = args.pop_front().ok_or(WrongArgAmount)?.to_atom()?;
+    let second = args.pop_front().ok_or(WrongArgAmount)?;
+    args.push_front(first.clone().get_value());
+    let result = apply(second, args);
+    first.0.replace(result.clone()?);
+    result
+}
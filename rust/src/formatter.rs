@@ -0,0 +1,263 @@
+use lexer::{Lexer, Token, TokenKind};
+use reader::Reader;
+use std::iter;
+use std::iter::Peekable;
+use types::ParseError;
+use types::ParseError::{MissingForm, NoClosingParen, UnexpectedClose};
+
+/// Lays out the forms in `source` to fit in `width` columns where it can. Works on the
+/// tokens rather than the forms read from them, so comments, `#_` forms and reader
+/// macros like `'` and `@` come out as they were written.
+pub fn format(source: &str, width: usize) -> Result<String, ParseError> {
+    // let the reader report anything it would reject, like invalid numbers
+    for form in Reader::new(source.as_bytes()) {
+        form?;
+    }
+    let mut parser = Parser {
+        source,
+        tokens: Lexer::new(source).peekable(),
+        last_end: 0,
+    };
+    let mut out = Writer {
+        text: String::new(),
+        column: 0,
+        width,
+    };
+    while let Some(token) = parser.tokens.next() {
+        let newlines = source[parser.last_end..token.span.start].matches('\n').count();
+        let node = parser.node(token)?;
+        if !out.text.is_empty() {
+            match node.kind {
+                Kind::Comment { trailing: true, .. } => out.push(" "),
+                // keep one blank line wherever the source had some
+                _ if newlines > 1 => out.push("\n\n"),
+                _ => out.push("\n"),
+            }
+        }
+        node.write(&mut out, 0);
+    }
+    Ok(out.text)
+}
+
+/// A form as written, with the comments inside it.
+struct Node<'a> {
+    kind: Kind<'a>,
+    /// How many columns the node takes on one line, or `None` if a `;` comment in it has
+    /// to end a line.
+    flat: Option<usize>,
+}
+
+enum Kind<'a> {
+    /// A symbol, number, string or block comment, kept as it was written.
+    Text(&'a str),
+    /// A `;` comment. `trailing` if it started on the line of the token before it.
+    Comment { text: &'a str, trailing: bool },
+    /// A reader macro like `'`, `@` or `#_`, then the form it applies to. `^` takes two
+    /// forms, the metadata and then its target. Comments between them are kept too.
+    Prefix(&'a str, Vec<Node<'a>>),
+    /// A list, vector or map with its opening and closing brackets.
+    Seq(char, char, Vec<Node<'a>>),
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Peekable<Lexer<'a>>,
+    /// Where the last token read ended.
+    last_end: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn node(&mut self, token: Token) -> Result<Node<'a>, ParseError> {
+        let text = token.span.text(self.source);
+        let trailing = !self.source[self.last_end..token.span.start].contains('\n');
+        self.last_end = token.span.end;
+        Ok(match token.kind {
+            TokenKind::Open(open) => {
+                let close = match open {
+                    '(' => ')',
+                    '[' => ']',
+                    _ => '}',
+                };
+                let mut items = Vec::new();
+                loop {
+                    let token = self.tokens.next().ok_or(NoClosingParen(close))?;
+                    if token.kind == TokenKind::Close(close) {
+                        self.last_end = token.span.end;
+                        break;
+                    }
+                    items.push(self.node(token)?);
+                }
+                Node {
+                    flat: spaced(&items).map(|w| w + 2),
+                    kind: Kind::Seq(open, close, items),
+                }
+            }
+            TokenKind::Close(c) => return Err(UnexpectedClose(c)),
+            TokenKind::Quote
+            | TokenKind::Quasiquote
+            | TokenKind::Unquote
+            | TokenKind::SpliceUnquote
+            | TokenKind::Deref
+            | TokenKind::Meta
+            | TokenKind::Discard => {
+                let mut forms = if token.kind == TokenKind::Meta { 2 } else { 1 };
+                let mut operand = Vec::new();
+                while forms > 0 {
+                    let token = match self.tokens.peek() {
+                        None | Some(Token {
+                            kind: TokenKind::Close(_),
+                            ..
+                        }) => return Err(MissingForm(text.to_string())),
+                        Some(_) => self.tokens.next().unwrap(),
+                    };
+                    let node = self.node(token)?;
+                    if !matches!(node.kind, Kind::Comment { .. }) {
+                        forms -= 1;
+                    }
+                    operand.push(node);
+                }
+                Node {
+                    flat: spaced(&operand).map(|w| w + text.chars().count()),
+                    kind: Kind::Prefix(text, operand),
+                }
+            }
+            TokenKind::Comment => Node {
+                kind: Kind::Comment { text, trailing },
+                flat: None,
+            },
+            TokenKind::UnterminatedString => return Err(ParseError::UnterminatedString),
+            TokenKind::UnterminatedComment => return Err(ParseError::UnterminatedComment),
+            TokenKind::String | TokenKind::Number | TokenKind::Symbol | TokenKind::BlockComment => {
+                Node {
+                    kind: Kind::Text(text),
+                    flat: Some(text.chars().count()),
+                }
+            }
+        })
+    }
+}
+
+/// The columns `nodes` take on one line with a space between each.
+fn spaced(nodes: &[Node]) -> Option<usize> {
+    nodes
+        .iter()
+        .map(|node| node.flat)
+        .sum::<Option<usize>>()
+        .map(|width| width + nodes.len().saturating_sub(1))
+}
+
+/// The formatted text so far, and the column the next character written lands in.
+struct Writer {
+    text: String,
+    column: usize,
+    width: usize,
+}
+
+impl Writer {
+    fn push(&mut self, text: &str) {
+        match text.rfind('\n') {
+            Some(i) => self.column = text[i + 1..].chars().count(),
+            None => self.column += text.chars().count(),
+        }
+        self.text.push_str(text);
+    }
+
+    fn newline(&mut self, indent: usize) {
+        self.text.push('\n');
+        self.text.extend(iter::repeat_n(' ', indent));
+        self.column = indent;
+    }
+
+    /// Whether `columns` more columns fit on the current line after a space.
+    fn fits(&self, columns: Option<usize>) -> bool {
+        columns.is_some_and(|c| self.column + 1 + c <= self.width)
+    }
+}
+
+impl<'a> Node<'a> {
+    /// Writes the node on one line. Only called on nodes with a `flat` width.
+    fn write_flat(&self, out: &mut Writer) {
+        match &self.kind {
+            Kind::Text(text) | Kind::Comment { text, .. } => out.push(text),
+            Kind::Prefix(mark, operand) => {
+                out.push(mark);
+                write_spaced(operand, out);
+            }
+            Kind::Seq(open, close, items) => {
+                out.push(open.encode_utf8(&mut [0; 4]));
+                write_spaced(items, out);
+                out.push(close.encode_utf8(&mut [0; 4]));
+            }
+        }
+    }
+
+    /// Writes the node starting at column `indent`, breaking lists, vectors and maps over
+    /// several lines when they don't fit in the writer's width.
+    fn write(&self, out: &mut Writer, indent: usize) {
+        if self.flat.is_some_and(|flat| indent + flat <= out.width) {
+            return self.write_flat(out);
+        }
+        match &self.kind {
+            Kind::Text(text) | Kind::Comment { text, .. } => out.push(text),
+            Kind::Prefix(mark, operand) => {
+                out.push(mark);
+                let inner = indent + mark.chars().count();
+                for (i, node) in operand.iter().enumerate() {
+                    if i > 0 {
+                        match operand[i - 1].kind {
+                            Kind::Comment { .. } => out.newline(inner),
+                            _ => out.push(" "),
+                        }
+                    }
+                    node.write(out, out.column);
+                }
+            }
+            Kind::Seq(open, close, items) => {
+                out.push(open.encode_utf8(&mut [0; 4]));
+                let inner = if *open == '(' { indent + 2 } else { indent + 1 };
+                let call = *open == '(' && matches!(items.first(), Some(Node { kind: Kind::Text(_), .. }));
+                let mut forms = 0;
+                for (i, item) in items.iter().enumerate() {
+                    let after_comment = i > 0 && matches!(items[i - 1].kind, Kind::Comment { .. });
+                    let same_line = match item.kind {
+                        Kind::Comment { trailing, .. } => trailing,
+                        _ if after_comment => false,
+                        // keep the first argument of a call on the line of its operator
+                        _ if call && forms == 1 => out.fits(item.flat),
+                        // and each value of a map on the line of its key
+                        _ => *open == '{' && forms % 2 == 1,
+                    };
+                    if i > 0 {
+                        if same_line {
+                            out.push(" ");
+                        } else {
+                            out.newline(inner);
+                        }
+                    }
+                    item.write(out, out.column);
+                    if !matches!(item.kind, Kind::Comment { .. }) {
+                        forms += 1;
+                    }
+                }
+                if let Some(Node {
+                    kind: Kind::Comment { .. },
+                    ..
+                }) = items.last()
+                {
+                    out.newline(indent);
+                }
+                out.push(close.encode_utf8(&mut [0; 4]));
+            }
+        }
+    }
+}
+
+/// Writes `nodes` on one line with a space between each.
+fn write_spaced(nodes: &[Node], out: &mut Writer) {
+    for (i, node) in nodes.iter().enumerate() {
+        if i > 0 {
+            out.push(" ");
+        }
+        node.write_flat(out);
+    }
+}
@@ -1,25 +1,38 @@
-use std::cell::RefCell;
+use analyzer::{analyze_function, Expr};
+use compiler::{compile_function, Chunk};
 use env::Env;
+use gc;
 use im_rc::Vector;
+use std::cell::OnceCell;
 use std::fmt::{Debug, Formatter};
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 use types::EvalError::WrongArgAmount;
-use types::{EvalError, EvalResult};
+use types::{EvalError, EvalResult, MidResult, Sym};
 use MalType;
 use MalType::{Bool, Float, Integer, Nil};
-use reader::read_str;
+use reader::{read_all, read_str};
+use sandbox;
+use debugger;
+use log::Level;
+use logger;
 
 pub fn default_env_non_native() -> Vec<String> {
-    let mut v = Vec::new();
-    v.push("(def! not (fn* (a) (if a false true)))");
-    v.push(r#"(def! load-file (fn* (f) (eval (read-string (str "(do " (slurp f) "\nnil)")))))"#);
+    let v = [
+        "(def! not (fn* (a) (if a false true)))",
+    ];
     v.iter().map(|s| s.to_string()).collect()
 }
 pub fn default_env() -> Env {
     let env = Env::new();
+    natives().into_iter().for_each(|(k, f)| {
+        env.set(k, Functions::new_native(f));
+    });
+    env
+}
 
-    let v: Vec<(&str, SimpleFn)> = vec![
+pub fn natives() -> Vec<(&'static str, SimpleFn)> {
+    vec![
         ("+", add),
         ("-", subtract),
         ("*", times),
@@ -38,18 +51,26 @@ pub fn default_env() -> Env {
         ("str", str),
         ("println", println),
         ("read-string", read_string),
+        ("read-all", read_all_forms),
         ("slurp", slurp),
         ("atom", atom),
         ("atom?", is_atom),
         ("deref", deref),
         ("reset!", reset),
-    ];
-    v.into_iter().for_each(|(k, f)| {
-        env.set(k.into(), Functions::new_native(f));
-    });
-    env
-}
-type SimpleFn = fn(Vector<MalType>) -> EvalResult;
+        ("set-log-level!", set_log_level),
+        ("log/info", log_info),
+        ("log/warn", log_warn),
+        ("log/error", log_error),
+        ("break-fn", break_fn),
+        ("unbreak-fn", unbreak_fn),
+        ("trace!", trace),
+        ("trace-fn", trace_fn),
+        ("untrace-fn", untrace_fn),
+        ("gc", collect_garbage),
+        ("gc-stats", gc_stats),
+    ]
+}
+pub type SimpleFn = fn(Vector<MalType>) -> EvalResult;
 
 #[derive(Clone)]
 pub enum Functions {
@@ -59,7 +80,10 @@ pub enum Functions {
 
 impl Debug for InnerFunction {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?} -> {}", self.params, self.ast)
+        match &self.name {
+            Some(name) => write!(f, "{} {:?} -> {}", name, self.params, self.ast),
+            None => write!(f, "{:?} -> {}", self.params, self.ast),
+        }
     }
 }
 
@@ -67,11 +91,57 @@ pub struct InnerFunction {
     pub ast: MalType,
     pub params: Vector<MalType>,
     pub env: Env,
+    pub name: Option<Sym>,
+    /// The body analysed for the tree-walking evaluator, when the closure is created or
+    /// on the first call.
+    pub expr: OnceCell<Rc<Expr>>,
+    /// The body compiled for the VM, on the first call from it.
+    pub code: OnceCell<Rc<Chunk>>,
+}
+
+impl InnerFunction {
+    /// Builds the closure for `(fn* name? (params) body)`. A named function gets
+    /// its own frame binding the name to itself so the body can recurse without `def!`.
+    pub fn closure(name: Option<Sym>, params: Vector<MalType>, ast: MalType, env: &Env) -> MalType {
+        let env = match name {
+            Some(_) => env.new_env(),
+            None => env.clone(),
+        };
+        let f = Rc::new(InnerFunction {
+            ast,
+            params,
+            env: env.clone(),
+            name: name.clone(),
+            expr: OnceCell::new(),
+            code: OnceCell::new(),
+        });
+        gc::track_function(&f);
+        let fun = MalType::NonNativeFunction(f);
+        if let Some(name) = name {
+            env.set(name, fun.clone());
+        }
+        fun
+    }
+
+    /// The analysed body, analysing it if the closure was not created by the tree walker.
+    pub fn expr(&self) -> MidResult<Rc<Expr>> {
+        if let Some(expr) = self.expr.get() {
+            return Ok(expr.clone());
+        }
+        let expr = analyze_function(self.name.clone(), &self.params, &self.ast)?;
+        let _ = self.expr.set(expr.clone());
+        Ok(expr)
+    }
+
+    /// The compiled body, compiling it if this is the first call from the VM.
+    pub fn code(&self) -> Rc<Chunk> {
+        self.code.get_or_init(|| Rc::new(compile_function(self.name.clone(), &self.params, &self.ast))).clone()
+    }
 }
 
-impl Into<EvalResult> for InnerFunction{
-    fn into(self) -> EvalResult {
-        Ok(MalType::NonNativeFunction(Rc::new(self)))
+impl From<InnerFunction> for EvalResult{
+    fn from(val: InnerFunction) -> Self {
+        Ok(MalType::NonNativeFunction(Rc::new(val)))
     }
 }
 
@@ -111,7 +181,7 @@ impl Debug for Functions {
 
 impl PartialEq<Self> for Functions {
     fn eq(&self, _other: &Self) -> bool {
-        return false;
+        false
     }
 }
 
@@ -131,26 +201,28 @@ impl Functions {
 
 impl Functions {
     pub fn call(&self, args: Vector<MalType>) -> EvalResult {
-        match self {
+        let result = match self {
             Functions::Native(f) => f(args),
             Functions::NonNative(f) => f(args),
-        }
+        }?;
+        sandbox::charge(&result)?;
+        Ok(result)
     }
 }
 
 pub fn add(args: Vector<MalType>) -> EvalResult {
     let mut result = Integer(0);
     for arg in args.into_iter() {
-        result = match (result.clone(), arg.clone()) {
+        result = match (&result, &arg) {
             (Integer(a), Integer(b)) => Integer(a + b),
-            _ => Float((result.clone().to_float()? + arg.clone().to_float()?).into()),
+            _ => Float((result.to_float()? + arg.to_float()?).into()),
         }
     }
     Ok(result)
 }
 
 pub fn subtract(mut args: Vector<MalType>) -> EvalResult {
-    let mut result = args.pop_front().ok_or(WrongArgAmount)?.clone();
+    let mut result = args.pop_front().ok_or(WrongArgAmount)?;
 
     for arg in args.into_iter() {
         result = match (&result, &arg) {
@@ -171,7 +243,7 @@ pub fn times(mut args: Vector<MalType>) -> EvalResult {
     Ok(result)
 }
 pub fn int_divide(mut args: Vector<MalType>) -> EvalResult {
-    let mut result = args.pop_front().ok_or(WrongArgAmount)?.clone();
+    let mut result = args.pop_front().ok_or(WrongArgAmount)?;
 
     for arg in args.into_iter() {
         result = match (&result, &arg) {
@@ -275,10 +347,16 @@ fn read_string(mut args: Vector<MalType>) -> EvalResult{
     Ok(read_str(str)?)
 }
 
+fn read_all_forms(mut args: Vector<MalType>) -> EvalResult {
+    let str = args.pop_front().ok_or(WrongArgAmount)?.to_mal_string()?;
+    Ok(MalType::List(read_all(str.into_iter().collect())?))
+}
+
 fn slurp(mut args: Vector<MalType>) -> EvalResult {
     let file_name = args.pop_front().ok_or(WrongArgAmount)?.to_mal_string()?;
     let str = file_name.into_iter().collect::<String>();
-    let file = std::fs::read_to_string(str.clone()).map_err(|e| EvalError::InvalidFile(str))?;
+    sandbox::check_path(&str)?;
+    let file = std::fs::read_to_string(str.clone()).map_err(|_| EvalError::InvalidFile(str))?;
     Ok(file.into())
 }
 
@@ -304,4 +382,96 @@ fn reset(mut args: Vector<MalType>) -> EvalResult{
     Ok(second)
 }
 
+/// `(set-log-level! :debug)`; the level may also be given as a string.
+fn set_log_level(mut args: Vector<MalType>) -> EvalResult {
+    let level = match args.pop_front().ok_or(WrongArgAmount)? {
+        MalType::Symbol(s) => s.trim_start_matches(':').to_string(),
+        other => other.to_mal_string()?.into_iter().collect(),
+    };
+    let level = logger::parse_level(&level).map_err(|_| EvalError::InvalidLogLevel(level))?;
+    logger::set_level(level);
+    Ok(Nil)
+}
+
+fn log_at(level: Level, args: Vector<MalType>) -> EvalResult {
+    let message = args
+        .into_iter()
+        .map(|x| x.pr_str(false))
+        .collect::<Vec<String>>()
+        .join(" ");
+    logger::log_message(level, &message);
+    Ok(Nil)
+}
+
+fn log_info(args: Vector<MalType>) -> EvalResult {
+    log_at(Level::Info, args)
+}
+
+fn log_warn(args: Vector<MalType>) -> EvalResult {
+    log_at(Level::Warn, args)
+}
 
+fn log_error(args: Vector<MalType>) -> EvalResult {
+    log_at(Level::Error, args)
+}
+
+/// `(gc)` frees the environments, functions and atoms that only refer to each other once
+/// the top-level form calling it has finished, when nothing being evaluated can still
+/// need them. `(gc-stats)` shows what it freed.
+fn collect_garbage(_args: Vector<MalType>) -> EvalResult {
+    gc::request();
+    Ok(Nil)
+}
+
+/// `(gc-stats)` is a map of the live environments, functions and atoms, and of how many
+/// collections have run and freed.
+fn gc_stats(_args: Vector<MalType>) -> EvalResult {
+    let stats = gc::stats();
+    let entries = [
+        (":envs", stats.envs),
+        (":functions", stats.functions),
+        (":atoms", stats.atoms),
+        (":collections", stats.collections),
+        (":collected", stats.collected),
+    ];
+    Ok(MalType::HashMap(
+        entries
+            .iter()
+            .map(|(k, v)| (MalType::Symbol((*k).into()), Integer(*v as i64)))
+            .collect(),
+    ))
+}
+
+fn trace(mut args: Vector<MalType>) -> EvalResult {
+    let on = args.pop_front().ok_or(WrongArgAmount)?.to_bool();
+    logger::set_tracing(on);
+    Ok(Bool(on))
+}
+
+/// The function name given to `trace-fn`, `break-fn` and friends, as a symbol or a string.
+fn function_name(mut args: Vector<MalType>) -> Result<String, EvalError> {
+    match args.pop_front().ok_or(WrongArgAmount)? {
+        MalType::Symbol(name) => Ok(name.to_string()),
+        other => Ok(other.to_mal_string()?.into_iter().collect()),
+    }
+}
+
+fn trace_fn(args: Vector<MalType>) -> EvalResult {
+    logger::trace_function(&function_name(args)?);
+    Ok(Nil)
+}
+
+fn untrace_fn(args: Vector<MalType>) -> EvalResult {
+    logger::untrace_function(&function_name(args)?);
+    Ok(Nil)
+}
+
+fn break_fn(args: Vector<MalType>) -> EvalResult {
+    debugger::set_breakpoint(&function_name(args)?);
+    Ok(Nil)
+}
+
+fn unbreak_fn(args: Vector<MalType>) -> EvalResult {
+    debugger::clear_breakpoint(&function_name(args)?);
+    Ok(Nil)
+}
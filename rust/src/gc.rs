@@ -0,0 +1,270 @@
+use env::InnerEnv;
+use functions::InnerFunction;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::{Rc, Weak};
+use types::MalType;
+
+/// How many environments, functions and atoms may be created between the collections
+/// `maybe_collect` runs, while fewer than that outlive a collection. A larger heap waits
+/// for as many to be created as it has live, so the time spent collecting stays in
+/// proportion to the work done between collections.
+pub const COLLECT_EVERY: usize = 10_000;
+
+/// Environments, functions and atoms are reference counted, which frees them as soon as
+/// nothing refers to them, except when they refer to each other: a closure stored in the
+/// frame it captured, a named function's own frame, an atom holding itself. The collector
+/// finds such groups that nothing outside refers to and empties their frames and atoms,
+/// which lets reference counting free them.
+///
+/// Every environment, function and atom is registered here when it is created. A
+/// collection counts the references each one gets from the others; whatever has more
+/// references than that is in use from somewhere the collector cannot see, like the Rust
+/// stack, and so is everything it refers to. The rest is garbage. A reference held in
+/// storage a persistent list, vector or map may share with copies elsewhere is not
+/// counted, as one of those copies may be out of sight: the collector keeps what it
+/// refers to, and so never needs to look inside such storage, however many frames share
+/// it.
+#[derive(Default)]
+struct Heap {
+    envs: Vec<Weak<InnerEnv>>,
+    functions: Vec<Weak<InnerFunction>>,
+    atoms: Vec<Weak<RefCell<MalType>>>,
+    /// Registrations since the last collection.
+    created: usize,
+    /// Registrations at which `maybe_collect` next collects.
+    collect_at: usize,
+    /// Registrations at which dead entries are next dropped from the lists.
+    prune_at: usize,
+    /// Whether `request` asked for a collection at the next safe point.
+    requested: bool,
+    collections: usize,
+    collected: usize,
+}
+
+thread_local! {
+    static HEAP: RefCell<Heap> = RefCell::new(Heap {
+        prune_at: COLLECT_EVERY,
+        collect_at: COLLECT_EVERY,
+        ..Heap::default()
+    });
+}
+
+/// Memory use as seen by the collector.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    /// Live environment frames.
+    pub envs: usize,
+    /// Live mal functions.
+    pub functions: usize,
+    /// Live atoms.
+    pub atoms: usize,
+    /// Collections run on this thread.
+    pub collections: usize,
+    /// Environments, functions and atoms freed by collections so far.
+    pub collected: usize,
+}
+
+pub fn track_env(env: &Rc<InnerEnv>) {
+    HEAP.with(|h| {
+        let mut heap = h.borrow_mut();
+        heap.envs.push(Rc::downgrade(env));
+        heap.created();
+    })
+}
+
+pub fn track_function(f: &Rc<InnerFunction>) {
+    HEAP.with(|h| {
+        let mut heap = h.borrow_mut();
+        heap.functions.push(Rc::downgrade(f));
+        heap.created();
+    })
+}
+
+pub fn track_atom(atom: &Rc<RefCell<MalType>>) {
+    HEAP.with(|h| {
+        let mut heap = h.borrow_mut();
+        heap.atoms.push(Rc::downgrade(atom));
+        heap.created();
+    })
+}
+
+impl Heap {
+    fn created(&mut self) {
+        self.created += 1;
+        let registered = self.envs.len() + self.functions.len() + self.atoms.len();
+        // keeps the lists from growing with entries for values freed the usual way
+        if registered >= self.prune_at {
+            self.prune();
+            let live = self.envs.len() + self.functions.len() + self.atoms.len();
+            self.prune_at = (2 * live).max(COLLECT_EVERY);
+        }
+    }
+
+    fn prune(&mut self) {
+        self.envs.retain(|w| w.strong_count() > 0);
+        self.functions.retain(|w| w.strong_count() > 0);
+        self.atoms.retain(|w| w.strong_count() > 0);
+    }
+}
+
+pub fn stats() -> Stats {
+    HEAP.with(|h| {
+        let mut heap = h.borrow_mut();
+        heap.prune();
+        Stats {
+            envs: heap.envs.len(),
+            functions: heap.functions.len(),
+            atoms: heap.atoms.len(),
+            collections: heap.collections,
+            collected: heap.collected,
+        }
+    })
+}
+
+/// Asks for a collection at the next safe point, as `(gc)` does.
+pub fn request() {
+    HEAP.with(|h| h.borrow_mut().requested = true);
+}
+
+/// Collects if one was requested or enough has been created since the last collection.
+/// Call it at safe points only, like `eval::safe_point`.
+pub fn maybe_collect() {
+    let due = HEAP.with(|h| {
+        let heap = h.borrow();
+        heap.requested || heap.created >= heap.collect_at
+    });
+    if due {
+        collect();
+    }
+}
+
+enum Node {
+    Env(Rc<InnerEnv>),
+    Function(Rc<InnerFunction>),
+    Atom(Rc<RefCell<MalType>>),
+}
+
+impl Node {
+    fn id(&self) -> usize {
+        match self {
+            Node::Env(e) => Rc::as_ptr(e) as usize,
+            Node::Function(f) => Rc::as_ptr(f) as usize,
+            Node::Atom(a) => Rc::as_ptr(a) as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Env(e) => Rc::strong_count(e),
+            Node::Function(f) => Rc::strong_count(f),
+            Node::Atom(a) => Rc::strong_count(a),
+        }
+    }
+
+    /// Calls `edge` with the id of each node this one refers to by a reference of its own,
+    /// rather than one held in storage that may be shared. False if the node is borrowed
+    /// and can't be read.
+    fn edges(&self, edge: &mut dyn FnMut(usize)) -> bool {
+        match self {
+            Node::Env(e) => {
+                if let Some(outer) = e.outer() {
+                    edge(&**outer as *const InnerEnv as usize);
+                }
+                e.try_for_each_value(|value| values(value, edge))
+            }
+            Node::Function(f) => {
+                edge(&*f.env as *const InnerEnv as usize);
+                true
+            }
+            Node::Atom(a) => match a.try_borrow() {
+                Ok(value) => {
+                    values(&value, edge);
+                    true
+                }
+                Err(_) => false,
+            },
+        }
+    }
+
+    /// Drops what this node refers to, returning it so it is freed after the collector
+    /// lets go of its nodes.
+    fn clear(&self) -> Option<MalType> {
+        match self {
+            Node::Env(e) => {
+                e.clear();
+                None
+            }
+            Node::Function(_) => None,
+            Node::Atom(a) => Some(a.replace(MalType::Nil)),
+        }
+    }
+}
+
+/// Finds the references to functions and atoms in `value` that are not in storage that
+/// may be shared. Those in shared storage are not counted, so what they refer to is kept
+/// whatever else refers to it, and the storage is not searched: a list held by many
+/// frames would otherwise be searched once for each of them.
+fn values(value: &MalType, edge: &mut dyn FnMut(usize)) {
+    match value {
+        // only a vector short enough to be stored inline copies its items when cloned;
+        // longer ones share their chunks between copies, as maps always do
+        MalType::List(l) | MalType::Vector(l) if l.is_inline() => l.iter().for_each(|v| values(v, edge)),
+        MalType::NonNativeFunction(f) => edge(Rc::as_ptr(f) as usize),
+        MalType::Atom(a) => edge(Rc::as_ptr(&a.0) as usize),
+        _ => {}
+    }
+}
+
+/// Frees the environments, functions and atoms only reachable from each other and
+/// returns how many there were.
+pub fn collect() -> usize {
+    let nodes = HEAP.with(|h| {
+        let mut heap = h.borrow_mut();
+        heap.prune();
+        heap.created = 0;
+        heap.requested = false;
+        heap.collections += 1;
+        let envs = heap.envs.iter().filter_map(Weak::upgrade).map(Node::Env);
+        let functions = heap.functions.iter().filter_map(Weak::upgrade).map(Node::Function);
+        let atoms = heap.atoms.iter().filter_map(Weak::upgrade).map(Node::Atom);
+        envs.chain(functions).chain(atoms).collect::<Vec<Node>>()
+    });
+    let index: HashMap<usize, usize> = nodes.iter().enumerate().map(|(i, n)| (n.id(), i)).collect();
+    // references from outside the nodes, less the one `nodes` holds
+    let mut outside: Vec<usize> = nodes.iter().map(|n| n.strong_count() - 1).collect();
+    let mut targets: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+    let mut unreadable = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        let readable = node.edges(&mut |id| {
+            if let Some(&target) = index.get(&id) {
+                targets[i].push(target);
+                outside[target] -= 1;
+            }
+        });
+        if !readable {
+            unreadable.push(i);
+        }
+    }
+    let mut reachable = vec![false; nodes.len()];
+    let mut pending: Vec<usize> = (0..nodes.len()).filter(|&i| outside[i] > 0).collect();
+    pending.extend(unreadable);
+    while let Some(i) = pending.pop() {
+        if !reachable[i] {
+            reachable[i] = true;
+            pending.extend(targets[i].iter().filter(|&&t| !reachable[t]));
+        }
+    }
+    let garbage: Vec<&Node> = nodes.iter().zip(&reachable).filter(|(_, r)| !**r).map(|(n, _)| n).collect();
+    let freed = garbage.len();
+    let cleared: Vec<Option<MalType>> = garbage.iter().map(|n| n.clear()).collect();
+    let live = nodes.len() - freed;
+    drop(nodes);
+    drop(cleared);
+    HEAP.with(|h| {
+        let mut heap = h.borrow_mut();
+        heap.collected += freed;
+        heap.collect_at = live.max(COLLECT_EVERY);
+    });
+    freed
+}
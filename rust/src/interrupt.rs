@@ -0,0 +1,56 @@
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use types::{EvalError, MidResult};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Asks the running evaluation to stop with `EvalError::Interrupted`. Safe to call from a
+/// signal handler or another thread.
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Clears a pending interrupt, e.g. one that arrived while no form was running.
+pub fn clear() {
+    INTERRUPTED.store(false, Ordering::SeqCst);
+}
+
+/// Routes SIGINT (Ctrl-C) to `interrupt` instead of killing the process. A handler
+/// installed before, by an earlier call or by the host, is kept.
+pub fn catch_sigint() -> Result<(), ctrlc::Error> {
+    match ctrlc::set_handler(interrupt) {
+        Err(ctrlc::Error::MultipleHandlers) => Ok(()),
+        result => result,
+    }
+}
+
+/// Called by the eval loop on every step.
+pub fn check() -> MidResult<()> {
+    if INTERRUPTED.swap(false, Ordering::SeqCst) {
+        return Err(EvalError::Interrupted);
+    }
+    match DEADLINE.with(|d| d.get()) {
+        Some(deadline) if Instant::now() >= deadline => Err(EvalError::Timeout),
+        _ => Ok(()),
+    }
+}
+
+/// Runs `f` with a wall-clock deadline of `timeout` from now. An earlier deadline that is
+/// already in effect is kept.
+pub fn with_timeout<T, F: FnOnce() -> T>(timeout: Duration, f: F) -> T {
+    let previous = DEADLINE.with(|d| d.get());
+    let deadline = Instant::now() + timeout;
+    let deadline = match previous {
+        Some(previous) if previous < deadline => previous,
+        _ => deadline,
+    };
+    DEADLINE.with(|d| d.set(Some(deadline)));
+    let result = f();
+    DEADLINE.with(|d| d.set(previous));
+    result
+}
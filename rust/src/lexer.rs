@@ -0,0 +1,195 @@
+/// What a token is, as far as can be told without reading the forms around it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    /// `(`, `[` or `{`.
+    Open(char),
+    /// `)`, `]` or `}`.
+    Close(char),
+    /// `'`
+    Quote,
+    /// `` ` ``
+    Quasiquote,
+    /// `~`
+    Unquote,
+    /// `~@`
+    SpliceUnquote,
+    /// `@`
+    Deref,
+    /// `^`
+    Meta,
+    /// A string literal, quotes included.
+    String,
+    /// A string missing its closing quote, which runs to the end of the text.
+    UnterminatedString,
+    /// An integer or float, or something that starts like one.
+    Number,
+    /// Any other run of characters: symbols, keywords, `nil`, `true` and `false`.
+    Symbol,
+    /// A `;` comment, up to the end of its line.
+    Comment,
+    /// A `#| ... |#` comment, which may hold other block comments.
+    BlockComment,
+    /// A block comment missing its closing `|#`, which runs to the end of the text.
+    UnterminatedComment,
+    /// `#_`, which drops the form after it.
+    Discard,
+}
+
+/// The byte offsets a token starts and ends at.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The token's text in the `source` it was read from.
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.start..self.end]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub span: Span,
+}
+
+/// Splits text into tokens, skipping whitespace and commas. Every character ends up in
+/// some token, so nothing is silently dropped.
+pub struct Lexer<'a> {
+    text: &'a str,
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(text: &'a str) -> Self {
+        Lexer { text, pos: 0 }
+    }
+
+    fn byte(&self, at: usize) -> Option<u8> {
+        self.text.as_bytes().get(at).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b) = self.byte(self.pos) {
+            if b.is_ascii_whitespace() || b == b',' {
+                self.pos += 1;
+            } else if b.is_ascii() {
+                return;
+            } else {
+                match self.text[self.pos..].chars().next() {
+                    Some(c) if c.is_whitespace() => self.pos += c.len_utf8(),
+                    _ => return,
+                }
+            }
+        }
+    }
+
+    /// The end of the string starting at `start`, and whether it has a closing quote.
+    fn string_end(&self, start: usize) -> (usize, bool) {
+        let bytes = self.text.as_bytes();
+        let mut at = start + 1;
+        while at < bytes.len() {
+            match bytes[at] {
+                b'\\' => at += 2,
+                b'"' => return (at + 1, true),
+                _ => at += 1,
+            }
+        }
+        (bytes.len(), false)
+    }
+
+    /// The end of the block comment starting at `start`, and whether it is closed.
+    fn block_comment_end(&self, start: usize) -> (usize, bool) {
+        let bytes = self.text.as_bytes();
+        let mut depth = 0;
+        let mut at = start;
+        while at + 1 < bytes.len() {
+            match (bytes[at], bytes[at + 1]) {
+                (b'#', b'|') => {
+                    depth += 1;
+                    at += 2;
+                }
+                (b'|', b'#') => {
+                    depth -= 1;
+                    at += 2;
+                    if depth == 0 {
+                        return (at, true);
+                    }
+                }
+                _ => at += 1,
+            }
+        }
+        (bytes.len(), false)
+    }
+
+    /// The end of the symbol or number starting at `start`.
+    fn word_end(&self, start: usize) -> usize {
+        let mut at = start;
+        while let Some(b) = self.byte(at) {
+            match b {
+                b'[' | b']' | b'{' | b'}' | b'(' | b')' | b'\'' | b'"' | b'`' | b',' | b';' => break,
+                _ if b.is_ascii_whitespace() => break,
+                _ if b.is_ascii() => at += 1,
+                _ => match self.text[at..].chars().next() {
+                    Some(c) if c.is_whitespace() => break,
+                    Some(c) => at += c.len_utf8(),
+                    None => break,
+                },
+            }
+        }
+        at
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let first = self.byte(start)?;
+        let (kind, end) = match first {
+            b'(' | b'[' | b'{' => (TokenKind::Open(first as char), start + 1),
+            b')' | b']' | b'}' => (TokenKind::Close(first as char), start + 1),
+            b'\'' => (TokenKind::Quote, start + 1),
+            b'`' => (TokenKind::Quasiquote, start + 1),
+            b'~' if self.byte(start + 1) == Some(b'@') => (TokenKind::SpliceUnquote, start + 2),
+            b'~' => (TokenKind::Unquote, start + 1),
+            b'@' => (TokenKind::Deref, start + 1),
+            b'^' => (TokenKind::Meta, start + 1),
+            b'"' => match self.string_end(start) {
+                (end, true) => (TokenKind::String, end),
+                (end, false) => (TokenKind::UnterminatedString, end),
+            },
+            b'#' if self.byte(start + 1) == Some(b'_') => (TokenKind::Discard, start + 2),
+            b'#' if self.byte(start + 1) == Some(b'|') => match self.block_comment_end(start) {
+                (end, true) => (TokenKind::BlockComment, end),
+                (end, false) => (TokenKind::UnterminatedComment, end),
+            },
+            b';' => {
+                let end = self.text[start..].find('\n').map_or(self.text.len(), |i| start + i);
+                (TokenKind::Comment, end)
+            }
+            _ => {
+                let end = self.word_end(start);
+                let number = first.is_ascii_digit()
+                    || ((first == b'-' || first == b'+')
+                        && self.byte(start + 1).is_some_and(|b| b.is_ascii_digit()));
+                let kind = if number { TokenKind::Number } else { TokenKind::Symbol };
+                (kind, end)
+            }
+        };
+        self.pos = end;
+        Some(Token {
+            kind,
+            span: Span { start, end },
+        })
+    }
+}
+
+/// Every token in `text`, comments included.
+pub fn tokenize(text: &str) -> Vec<Token> {
+    Lexer::new(text).collect()
+}
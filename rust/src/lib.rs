@@ -1,14 +1,30 @@
 extern crate core;
+extern crate ctrlc;
 extern crate im_rc;
 extern crate lazy_static;
 extern crate log;
-extern crate regex;
+extern crate rustyline;
 
 use types::MalType;
 
+pub mod analyzer;
+pub mod compiler;
+pub mod debugger;
 pub mod env;
+pub mod eval;
+pub mod formatter;
 pub mod functions;
+pub mod gc;
+pub mod interrupt;
+pub mod lexer;
 pub mod logger;
+pub mod loops;
 pub mod printer;
+pub mod profiler;
 pub mod reader;
+pub mod repl;
+mod resolve;
+pub mod sandbox;
+pub mod symbol;
 pub mod types;
+pub mod vm;
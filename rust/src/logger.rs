@@ -1,26 +1,308 @@
-use log::Level::Warn;
-use log::{Log, Metadata, Record};
+use lazy_static::lazy_static;
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::env;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-pub struct MalLogger;
+/// The environment variable read by `init`, e.g. `MAL_LOG=debug,format=json,file=mal.log`.
+pub const LOG_VAR: &str = "MAL_LOG";
+
+/// How each log line is written.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    /// Just the message.
+    Plain,
+    /// `2024-01-31T12:00:00.000Z WARN message`
+    Timestamp,
+    /// One JSON object per line with `ts`, `level`, `target` and `msg`.
+    Json,
+}
+
+/// Where log lines go.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    Stderr,
+    File(PathBuf),
+}
+
+/// The level, target and format of the logger.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogConfig {
+    pub level: LevelFilter,
+    pub target: Target,
+    pub format: Format,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: LevelFilter::Warn,
+            target: Target::Stderr,
+            format: Format::Plain,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Reads the config from `MAL_LOG`, falling back to the defaults when it is unset.
+    pub fn from_env() -> Result<LogConfig, String> {
+        match env::var(LOG_VAR) {
+            Ok(spec) => spec.parse(),
+            Err(_) => Ok(LogConfig::default()),
+        }
+    }
+}
+
+impl FromStr for LogConfig {
+    type Err = String;
+
+    /// Parses comma separated settings: a bare level, `level=`, `format=` (plain,
+    /// timestamp or json) and `file=` (a path, or `stderr`).
+    fn from_str(spec: &str) -> Result<LogConfig, String> {
+        let mut config = LogConfig::default();
+        for setting in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = match setting.find('=') {
+                Some(i) => (&setting[..i], &setting[i + 1..]),
+                None => ("level", setting),
+            };
+            match key {
+                "level" => config.level = parse_level(value)?,
+                "format" => {
+                    config.format = match value {
+                        "plain" => Format::Plain,
+                        "timestamp" => Format::Timestamp,
+                        "json" => Format::Json,
+                        _ => return Err(format!("unknown log format: {}", value)),
+                    }
+                }
+                "file" if value == "stderr" => config.target = Target::Stderr,
+                "file" => config.target = Target::File(PathBuf::from(value)),
+                _ => return Err(format!("unknown log setting: {}", key)),
+            }
+        }
+        Ok(config)
+    }
+}
+
+pub fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    level
+        .parse()
+        .map_err(|_| format!("unknown log level: {}", level))
+}
+
+pub struct MalLogger {
+    format: Mutex<Format>,
+    file: Mutex<Option<File>>,
+}
 
 impl Log for MalLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= Warn
+        metadata.level() <= log::max_level()
     }
 
     fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            println!("{}", record.args())
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format_record(*self.format.lock().unwrap(), record);
+        match self.file.lock().unwrap().as_mut() {
+            Some(file) => {
+                let _ = writeln!(file, "{}", line);
+            }
+            None => {
+                let _ = writeln!(io::stderr(), "{}", line);
+            }
         }
     }
 
-    fn flush(&self) {}
+    fn flush(&self) {
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            let _ = file.flush();
+        }
+    }
 }
 
-use log::{LevelFilter, SetLoggerError};
+fn format_record(format: Format, record: &Record) -> String {
+    match format {
+        Format::Plain => format!("{}", record.args()),
+        Format::Timestamp => format!("{} {} {}", timestamp(), record.level(), record.args()),
+        Format::Json => format!(
+            r#"{{"ts":"{}","level":"{}","target":{},"msg":{}}}"#,
+            timestamp(),
+            record.level(),
+            json_string(record.target()),
+            json_string(&record.args().to_string())
+        ),
+    }
+}
+
+/// The current UTC time in RFC 3339 form, with milliseconds.
+fn timestamp() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = now.as_secs() as i64;
+    let (days, day_secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        day_secs / 3600,
+        day_secs / 60 % 60,
+        day_secs % 60,
+        now.subsec_millis()
+    )
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
 
-static LOGGER: MalLogger = MalLogger;
+lazy_static! {
+    static ref LOGGER: MalLogger = MalLogger {
+        format: Mutex::new(Format::Plain),
+        file: Mutex::new(None),
+    };
+}
+
+/// Installs the logger configured by `MAL_LOG`.
+pub fn init() -> Result<(), String> {
+    init_with(LogConfig::from_env()?)
+}
+
+pub fn init_with(config: LogConfig) -> Result<(), String> {
+    log::set_logger(&*LOGGER).map_err(|e: SetLoggerError| e.to_string())?;
+    configure(config)
+}
+
+/// Changes the level, target and format of the installed logger.
+pub fn configure(config: LogConfig) -> Result<(), String> {
+    let file = match &config.target {
+        Target::Stderr => None,
+        Target::File(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("{}: {}", path.display(), e))?,
+        ),
+    };
+    *LOGGER.file.lock().unwrap() = file;
+    *LOGGER.format.lock().unwrap() = config.format;
+    set_level(config.level);
+    Ok(())
+}
+
+/// Changes the log level at runtime, as `(set-log-level! :debug)` does.
+pub fn set_level(level: LevelFilter) {
+    log::set_max_level(level);
+}
+
+/// Logs a message from mal code. Unlike the `log` macros this is not compiled out of
+/// release builds, so scripts can log at any level.
+pub fn log_message(level: Level, message: &str) {
+    log::logger().log(
+        &Record::builder()
+            .args(format_args!("{}", message))
+            .level(level)
+            .target("mal")
+            .build(),
+    );
+}
+
+static TRACING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static TRACED: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Turns tracing of every evaluated form on or off, as `(trace! true)` does.
+pub fn set_tracing(on: bool) {
+    TRACING.store(on, Ordering::SeqCst);
+}
+
+pub fn tracing() -> bool {
+    TRACING.load(Ordering::SeqCst)
+}
+
+/// Writes one trace line to stderr, indented by `depth`.
+pub fn trace(depth: usize, args: fmt::Arguments) {
+    eprintln!("{:width$}{}", "", args, width = depth * 2);
+}
+
+/// Traces a line from the step binaries when tracing is on.
+#[macro_export]
+macro_rules! mal_trace {
+    ($($arg:tt)*) => {
+        if $crate::logger::tracing() {
+            $crate::logger::trace(0, format_args!($($arg)*));
+        }
+    };
+}
+
+/// Traces calls to the function named `name`, as `(trace-fn 'name)` does.
+pub fn trace_function(name: &str) {
+    TRACED.with(|t| t.borrow_mut().insert(name.to_string()));
+}
+
+pub fn untrace_function(name: &str) {
+    TRACED.with(|t| t.borrow_mut().remove(name));
+}
+
+pub fn is_traced(name: &str) -> bool {
+    TRACED.with(|t| t.borrow().contains(name))
+}
+
+/// Whether any function is traced.
+pub fn tracing_functions() -> bool {
+    TRACED.with(|t| !t.borrow().is_empty())
+}
+
+/// Prints the entry of a traced call and indents what happens inside it.
+pub fn trace_call(call: fmt::Arguments) {
+    let depth = CALL_DEPTH.with(|d| d.replace(d.get() + 1));
+    trace(depth, format_args!("> {}", call));
+}
 
-pub fn init() -> Result<(), SetLoggerError> {
-    log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Warn))
+/// Prints the result of the traced call entered last.
+pub fn trace_return(result: fmt::Arguments) {
+    let depth = CALL_DEPTH.with(|d| {
+        d.set(d.get().saturating_sub(1));
+        d.get()
+    });
+    trace(depth, format_args!("< {}", result));
 }
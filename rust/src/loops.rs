@@ -0,0 +1,85 @@
+use env::Env;
+use im_rc::Vector;
+use types::{EvalError, MalType, MidResult};
+
+/// The target of a `recur`: the binding names of the innermost `loop`, its body,
+/// and the environment the loop was entered from. The body is the form itself, or
+/// whatever the evaluator made of it.
+pub struct Loop<B = MalType> {
+    pub params: Vector<MalType>,
+    pub body: B,
+    pub env: Env,
+}
+
+impl<B: Clone> Loop<B> {
+    /// Rebinds the loop names to `args` in a fresh frame and returns it along with the body
+    /// to continue with.
+    pub fn recur(&self, args: Vector<MalType>) -> MidResult<(Env, B)> {
+        if args.len() != self.params.len() {
+            return Err(EvalError::WrongArgAmount);
+        }
+        let env = self.env.new_bind(&self.params, args)?;
+        Ok((env, self.body.clone()))
+    }
+}
+
+/// Checks that every `recur` in a `loop` body is in tail position, before the loop runs.
+pub fn check_tail_recur(body: &MalType) -> MidResult<()> {
+    check(body, true)
+}
+
+fn check(form: &MalType, tail: bool) -> MidResult<()> {
+    match form {
+        MalType::List(l) => {
+            let head = match l.head() {
+                Some(MalType::Symbol(s)) => s.as_str(),
+                _ => return check_all(l.iter()),
+            };
+            let mut rest = l.iter().skip(1);
+            match head {
+                "recur" if !tail => Err(EvalError::RecurNotInTail(form.clone())),
+                "recur" => check_all(rest),
+                "if" => {
+                    check(rest.next().unwrap_or(&MalType::Nil), false)?;
+                    rest.try_for_each(|branch| check(branch, tail))
+                }
+                "do" => {
+                    let len = l.len();
+                    l.iter()
+                        .enumerate()
+                        .skip(1)
+                        .try_for_each(|(i, f)| check(f, tail && i == len - 1))
+                }
+                "let*" => {
+                    if let Some(bindings) = rest.next() {
+                        check_bindings(bindings)?;
+                    }
+                    rest.try_for_each(|body| check(body, tail))
+                }
+                // a nested loop is its own recur target, its body is checked when it runs
+                "loop" => match rest.next() {
+                    Some(bindings) => check_bindings(bindings),
+                    None => Ok(()),
+                },
+                "fn*" | "quote" => Ok(()),
+                _ => check_all(l.iter()),
+            }
+        }
+        MalType::Vector(v) => check_all(v.iter()),
+        MalType::HashMap(h) => h
+            .iter()
+            .try_for_each(|(k, v)| check(k, false).and_then(|_| check(v, false))),
+        _ => Ok(()),
+    }
+}
+
+fn check_all<'a, I: Iterator<Item = &'a MalType>>(mut forms: I) -> MidResult<()> {
+    forms.try_for_each(|f| check(f, false))
+}
+
+fn check_bindings(bindings: &MalType) -> MidResult<()> {
+    match bindings {
+        MalType::List(b) | MalType::Vector(b) => check_all(b.iter().skip(1).step_by(2)),
+        _ => Ok(()),
+    }
+}
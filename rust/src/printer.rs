@@ -1,68 +1,105 @@
-use std::borrow::Borrow;
-use std::fmt::{Display, Formatter};
+use std::cell::RefCell;
+use std::fmt::{self, Display, Formatter, Write};
+use std::rc::Rc;
 use types::MalType;
 use types::MalType::Float;
 
+thread_local! {
+    /// The atoms being printed, outermost first. An atom met again inside itself is
+    /// printed as `(atom ...)`.
+    static PRINTING: RefCell<Vec<*const RefCell<MalType>>> = const { RefCell::new(Vec::new()) };
+}
+
 impl Display for MalType {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.clone().pr_str(true))
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.write_to(f, true)
     }
 }
 
 impl MalType {
-    pub fn pr_str(self, print_readably: bool) -> String {
+    pub fn pr_str(&self, print_readably: bool) -> String {
+        let mut out = String::new();
+        self.write_to(&mut out, print_readably)
+            .expect("writing to a String cannot fail");
+        out
+    }
+
+    /// Prints to `out` as `pr_str` does, without building a string for each element.
+    pub fn write_to<W: Write>(&self, out: &mut W, print_readably: bool) -> fmt::Result {
         match self {
-            MalType::Nil => "nil".to_string(),
-            MalType::Bool(bool) => bool.to_string(),
-            MalType::Integer(n) => n.to_string(),
-            Float(n) => n.0.to_string(),
-            MalType::List(l) => {
-                let inner = l
-                    .into_iter()
-                    .map(|m| m.pr_str(print_readably))
-                    .collect::<Vec<String>>()
-                    .join(" ");
-                format!("({})", inner)
-            }
-            MalType::Vector(l) => {
-                let inner = l
-                    .into_iter()
-                    .map(|m| m.pr_str(print_readably))
-                    .collect::<Vec<String>>()
-                    .join(" ");
-                format!("[{}]", inner)
-            }
+            MalType::Nil => out.write_str("nil"),
+            MalType::Bool(bool) => write!(out, "{}", bool),
+            MalType::Integer(n) => write!(out, "{}", n),
+            Float(n) => write!(out, "{}", n.0),
+            MalType::List(l) => write_seq(out, '(', l.iter(), ')', print_readably),
+            MalType::Vector(l) => write_seq(out, '[', l.iter(), ']', print_readably),
             MalType::HashMap(h) => {
-                let inner = h
-                    .into_iter()
-                    .map(|(k, v)| {
-                        format!("{} {}", k.pr_str(print_readably), v.pr_str(print_readably))
-                    })
-                    .collect::<Vec<String>>()
-                    .join(" ");
-                format!("{{{}}}", inner)
+                out.write_char('{')?;
+                for (i, (k, v)) in h.iter().enumerate() {
+                    if i > 0 {
+                        out.write_char(' ')?;
+                    }
+                    k.write_to(out, print_readably)?;
+                    out.write_char(' ')?;
+                    v.write_to(out, print_readably)?;
+                }
+                out.write_char('}')
             }
-            MalType::Symbol(s) => s.to_string(),
+            MalType::Symbol(s) => out.write_str(s),
             MalType::String(s) => {
-                if print_readably {
-                    let str: String = s
-                        .into_iter()
-                        .map(|c| match c {
-                            '"' => "\\\"".to_string(),
-                            '\n' => "\\n".to_string(),
-                            '\\' => "\\\\".to_string(),
-                            _ => c.to_string(),
-                        })
-                        .collect();
-                    format!("\"{}\"", str)
-                } else {
-                    let string = s.iter().collect::<String>();
-                    string
+                if !print_readably {
+                    return s.iter().try_for_each(|c| out.write_char(*c));
+                }
+                out.write_char('"')?;
+                for c in s.iter() {
+                    match c {
+                        '"' => out.write_str("\\\"")?,
+                        '\n' => out.write_str("\\n")?,
+                        '\\' => out.write_str("\\\\")?,
+                        _ => out.write_char(*c)?,
+                    }
                 }
+                out.write_char('"')
             }
-            MalType::Function(_) => "#<function>".to_string(),
-            MalType::NonNativeFunction(_) => "#<function>".to_string(),
-            MalType::Atom(a) => {format!("(atom {})", a.get_value())}
+            MalType::Function(_) => out.write_str("#<function>"),
+            MalType::NonNativeFunction(f) => {
+                out.write_str("#<function ")?;
+                if let Some(name) = &f.name {
+                    write!(out, "{} ", name)?;
+                }
+                write_seq(out, '(', f.params.iter(), ')', print_readably)?;
+                out.write_char('>')
+            }
+            MalType::Atom(a) => {
+                let atom = Rc::as_ptr(&a.0);
+                if PRINTING.with(|p| p.borrow().contains(&atom)) {
+                    return out.write_str("(atom ...)");
+                }
+                PRINTING.with(|p| p.borrow_mut().push(atom));
+                let result = out
+                    .write_str("(atom ")
+                    .and_then(|_| a.0.borrow().write_to(out, print_readably))
+                    .and_then(|_| out.write_char(')'));
+                PRINTING.with(|p| p.borrow_mut().pop());
+                result
+            }
+        }
+    }
+}
+
+fn write_seq<'a, W: Write, I: Iterator<Item = &'a MalType>>(
+    out: &mut W,
+    open: char,
+    items: I,
+    close: char,
+    print_readably: bool,
+) -> fmt::Result {
+    out.write_char(open)?;
+    for (i, item) in items.enumerate() {
+        if i > 0 {
+            out.write_char(' ')?;
         }
+        item.write_to(out, print_readably)?;
     }
+    out.write_char(close)
 }
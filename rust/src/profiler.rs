@@ -0,0 +1,240 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Call counts and times of one function in a profile.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    pub calls: u64,
+    /// Time spent in the function and everything it called, counting recursive calls once.
+    pub inclusive: Duration,
+    /// Time spent in the function itself.
+    pub exclusive: Duration,
+}
+
+/// What the profiler recorded between `start` and `stop`.
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub total: Duration,
+    pub functions: HashMap<String, Stats>,
+    /// Every call stack seen, as a tree whose root is `stacks[0]`.
+    stacks: Vec<StackNode>,
+}
+
+/// One call stack: the stack of its parent node with one more call to `name` on top.
+#[derive(Debug)]
+struct StackNode {
+    name: String,
+    parent: usize,
+    /// Time spent in the function on top of this stack itself.
+    exclusive: Duration,
+}
+
+struct Call {
+    /// The function's index in `Recording::names`.
+    name: usize,
+    /// The `StackNode` of the stack this call is on top of.
+    node: usize,
+    start: Instant,
+    children: Duration,
+}
+
+struct Recording {
+    start: Instant,
+    stack: Vec<Call>,
+    /// Every function name called, with its stats and how many calls to it are running.
+    names: Vec<(String, Stats, u32)>,
+    ids: HashMap<String, usize>,
+    /// `nodes[n]` are the stacks one call deeper than node `n`, by function.
+    nodes: Vec<HashMap<usize, usize>>,
+    stacks: Vec<StackNode>,
+}
+
+thread_local! {
+    static ACTIVE: Cell<bool> = const { Cell::new(false) };
+    static RECORDING: RefCell<Option<Recording>> = const { RefCell::new(None) };
+}
+
+pub fn active() -> bool {
+    ACTIVE.with(|a| a.get())
+}
+
+/// Starts a new profile on this thread, discarding any unfinished one.
+pub fn start() {
+    RECORDING.with(|r| {
+        *r.borrow_mut() = Some(Recording {
+            start: Instant::now(),
+            stack: Vec::new(),
+            names: Vec::new(),
+            ids: HashMap::new(),
+            nodes: vec![HashMap::new()],
+            stacks: vec![StackNode {
+                name: String::new(),
+                parent: 0,
+                exclusive: Duration::default(),
+            }],
+        })
+    });
+    ACTIVE.with(|a| a.set(true));
+}
+
+/// Stops profiling and returns what was recorded.
+pub fn stop() -> Profile {
+    ACTIVE.with(|a| a.set(false));
+    RECORDING.with(|r| match r.borrow_mut().take() {
+        Some(recording) => Profile {
+            total: recording.start.elapsed(),
+            functions: recording
+                .names
+                .into_iter()
+                .map(|(name, stats, _)| (name, stats))
+                .collect(),
+            stacks: recording.stacks,
+        },
+        None => Profile::default(),
+    })
+}
+
+/// Runs `f` with profiling on and returns its result along with the profile.
+pub fn profile<T, F: FnOnce() -> T>(f: F) -> (T, Profile) {
+    start();
+    let result = f();
+    (result, stop())
+}
+
+/// A call being timed; the call ends when this is dropped.
+pub struct Frame(());
+
+/// Times a call to `name` until the returned frame is dropped, when profiling is on.
+pub fn enter(name: &str) -> Option<Frame> {
+    if !active() {
+        return None;
+    }
+    RECORDING.with(|r| {
+        r.borrow_mut().as_mut().map(|recording| {
+            recording.enter(name);
+            Frame(())
+        })
+    })
+}
+
+impl Drop for Frame {
+    fn drop(&mut self) {
+        RECORDING.with(|r| {
+            if let Some(recording) = r.borrow_mut().as_mut() {
+                recording.exit();
+            }
+        });
+    }
+}
+
+impl Recording {
+    fn enter(&mut self, name: &str) {
+        let id = match self.ids.get(name) {
+            Some(&id) => id,
+            None => {
+                self.names.push((name.to_string(), Stats::default(), 0));
+                self.ids.insert(name.to_string(), self.names.len() - 1);
+                self.names.len() - 1
+            }
+        };
+        self.names[id].2 += 1;
+        let parent = self.stack.last().map_or(0, |c| c.node);
+        let node = match self.nodes[parent].get(&id) {
+            Some(&node) => node,
+            None => {
+                self.stacks.push(StackNode {
+                    name: name.to_string(),
+                    parent,
+                    exclusive: Duration::default(),
+                });
+                self.nodes.push(HashMap::new());
+                let node = self.stacks.len() - 1;
+                self.nodes[parent].insert(id, node);
+                node
+            }
+        };
+        self.stack.push(Call {
+            name: id,
+            node,
+            start: Instant::now(),
+            children: Duration::default(),
+        });
+    }
+
+    fn exit(&mut self) {
+        let call = match self.stack.pop() {
+            Some(call) => call,
+            None => return,
+        };
+        let elapsed = call.start.elapsed();
+        let exclusive = elapsed.saturating_sub(call.children);
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children += elapsed;
+        }
+        let (_, stats, running) = &mut self.names[call.name];
+        *running -= 1;
+        stats.calls += 1;
+        stats.exclusive += exclusive;
+        // an outer call to the same function already counts this time
+        if *running == 0 {
+            stats.inclusive += elapsed;
+        }
+        self.stacks[call.node].exclusive += exclusive;
+    }
+}
+
+impl Profile {
+    /// A table of the profiled functions, most exclusive time first.
+    pub fn report(&self) -> String {
+        let mut functions: Vec<(&String, &Stats)> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+        let mut lines = vec![format!(
+            "{:>10} {:>12} {:>12}  function",
+            "calls", "incl ms", "excl ms"
+        )];
+        for (name, stats) in functions {
+            lines.push(format!(
+                "{:>10} {:>12.3} {:>12.3}  {}",
+                stats.calls,
+                millis(stats.inclusive),
+                millis(stats.exclusive),
+                name
+            ));
+        }
+        lines.push(format!("total {:.3} ms", millis(self.total)));
+        lines.join("\n")
+    }
+
+    /// Writes the call stacks in the folded format read by flamegraph.pl and inferno, one
+    /// line per stack weighted by exclusive time in microseconds. Stacks come depth first,
+    /// callees in name order. Each line is written as soon as it is built, since deep
+    /// recursion makes them long.
+    pub fn write_folded<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); self.stacks.len()];
+        for (i, node) in self.stacks.iter().enumerate().skip(1) {
+            children[node.parent].push(i);
+        }
+        for callees in &mut children {
+            callees.sort_by(|a, b| self.stacks[*a].name.cmp(&self.stacks[*b].name));
+        }
+        let mut path = String::new();
+        // the stacks left to write, each with the length of its parent's path
+        let mut pending: Vec<(usize, usize)> = children[0].iter().rev().map(|&n| (n, 0)).collect();
+        while let Some((node, parent)) = pending.pop() {
+            path.truncate(parent);
+            if parent > 0 {
+                path.push(';');
+            }
+            path.push_str(&self.stacks[node].name);
+            writeln!(out, "{} {}", path, self.stacks[node].exclusive.as_micros())?;
+            pending.extend(children[node].iter().rev().map(|&n| (n, path.len())));
+        }
+        Ok(())
+    }
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
@@ -1,138 +1,301 @@
 use im_rc::{HashMap, Vector};
-use lazy_static::lazy_static;
-use regex::Regex;
-use std::iter::Peekable;
-use std::vec::IntoIter;
+use lexer::{Lexer, Token, TokenKind};
+use std::io::{ErrorKind, Read};
+use std::str;
 use types::MalType::{Bool, Float, Integer, Nil, Symbol};
-use types::ParseError::NoClosingParen;
+use types::ParseError::{MissingForm, NoClosingParen, UnexpectedClose};
+use types::ParseError::{UnterminatedComment, UnterminatedString};
 use types::{MalType, ParseError, ParseResult};
 
-type Reader = Peekable<IntoIter<String>>;
+/// How many bytes a `Reader` asks its source for at least.
+const CHUNK_SIZE: usize = 8 * 1024;
 
-const REG_STRING: &str = r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]+)"#;
+pub fn read_str(text: String) -> ParseResult {
+    let tokens = tokenize(&text);
+    Ok(next_form(&mut Tokens::new(&text, &tokens))?.unwrap_or(Nil))
+}
 
-lazy_static! {
-    static ref REGEX: Regex = Regex::new(REG_STRING).expect("regex failed");
+/// Reads every top-level form in `text`.
+pub fn read_all(text: String) -> Result<Vector<MalType>, ParseError> {
+    let tokens = tokenize(&text);
+    let mut reader = Tokens::new(&text, &tokens);
+    let mut forms = Vector::new();
+    while let Some(form) = next_form(&mut reader)? {
+        forms.push_back(form);
+    }
+    Ok(forms)
 }
-pub fn read_str(text: String) -> ParseResult {
-    let mut tokenized_result = tokenize(text).into_iter().peekable();
-    read_form(&mut tokenized_result)
+
+/// Reads the top-level forms of a file, pipe or other stream one at a time, pulling in
+/// more input only when the text buffered so far ends inside a form. Reading stops at
+/// the first error.
+pub struct Reader<R> {
+    source: R,
+    /// The input from the end of the last form returned on.
+    text: String,
+    /// The start of a UTF-8 character split between two reads.
+    partial: Vec<u8>,
+    /// The tokens in `text`, and how many of them the forms returned so far took up.
+    tokens: Vec<Token>,
+    used: usize,
+    eof: bool,
+    failed: bool,
 }
 
-fn read_form(reader: &mut Reader) -> ParseResult {
-    // println!("{:?} form", reader);
-    let head = match reader.peek() {
-        None => return Nil.into(),
-        Some(v) => v,
-    };
+impl<R: Read> Reader<R> {
+    pub fn new(source: R) -> Self {
+        Reader {
+            source,
+            text: String::new(),
+            partial: Vec::new(),
+            tokens: Vec::new(),
+            used: 0,
+            eof: false,
+            failed: false,
+        }
+    }
+
+    /// Drops the forms already returned from the buffer and appends the next chunk of
+    /// input, at least as big as what is left so a long form is only re-read a few times.
+    fn fill(&mut self) -> Result<(), ParseError> {
+        let consumed = match self.used {
+            0 => 0,
+            used => self.tokens[used - 1].span.end,
+        };
+        self.text.drain(..consumed);
+        let mut chunk = vec![0; CHUNK_SIZE.max(self.text.len())];
+        let read = loop {
+            match self.source.read(&mut chunk) {
+                Ok(read) => break read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(ParseError::Io(e.to_string())),
+            }
+        };
+        self.partial.extend_from_slice(&chunk[..read]);
+        let valid = match str::from_utf8(&self.partial) {
+            Ok(text) => text.len(),
+            // a character cut off at the end of the chunk is completed by the next one
+            Err(e) if e.error_len().is_none() && read > 0 => e.valid_up_to(),
+            Err(_) => return Err(ParseError::Io("stream did not contain valid UTF-8".to_string())),
+        };
+        self.text.push_str(str::from_utf8(&self.partial[..valid]).expect("checked above"));
+        self.partial.drain(..valid);
+        self.eof = read == 0;
+        self.tokens = tokenize(&self.text);
+        self.used = 0;
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Reader<R> {
+    type Item = ParseResult;
+
+    fn next(&mut self) -> Option<ParseResult> {
+        while !self.failed {
+            let mut tokens = Tokens::new(&self.text, &self.tokens);
+            tokens.pos = self.used;
+            match next_form(&mut tokens) {
+                // a token running up to the end of the input may continue in the next chunk
+                Ok(Some(form)) if self.eof || tokens.complete() => {
+                    self.used = tokens.pos;
+                    return Some(Ok(form));
+                }
+                Ok(None) if self.eof => return None,
+                Err(e) if self.eof || !tokens.exhausted() => {
+                    self.failed = true;
+                    return Some(Err(e));
+                }
+                _ => {}
+            }
+            if let Err(e) = self.fill() {
+                self.failed = true;
+                return Some(Err(e));
+            }
+        }
+        None
+    }
+}
+
+/// The tokens of a text, comments left out.
+struct Tokens<'a> {
+    text: &'a str,
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(text: &'a str, tokens: &'a [Token]) -> Self {
+        Tokens { text, tokens, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn text(&self, token: Token) -> &'a str {
+        token.span.text(self.text)
+    }
+
+    /// Whether reading ran past the last token.
+    fn exhausted(&self) -> bool {
+        self.pos >= self.tokens.len()
+    }
+
+    /// Whether the last token read is known to be whole: it closes a collection or a
+    /// string, or something follows it.
+    fn complete(&self) -> bool {
+        match self.pos.checked_sub(1).and_then(|last| self.tokens.get(last)) {
+            Some(token) => match token.kind {
+                TokenKind::Close(_) | TokenKind::String => true,
+                _ => token.span.end < self.text.len(),
+            },
+            None => false,
+        }
+    }
+}
+
+/// Reads the next form that isn't discarded, if there is one.
+fn next_form(reader: &mut Tokens) -> Result<Option<MalType>, ParseError> {
+    skip_discarded(reader)?;
+    match reader.peek() {
+        Some(_) => read_form(reader).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Skips each `#_` ahead and the form it drops. `#_ #_ a b` drops both `a` and `b`.
+fn skip_discarded(reader: &mut Tokens) -> Result<(), ParseError> {
+    while reader.peek().map(|token| token.kind) == Some(TokenKind::Discard) {
+        reader.next();
+        skip_discarded(reader)?;
+        match reader.peek().map(|token| token.kind) {
+            None | Some(TokenKind::Close(_)) => return Err(MissingForm("#_".to_string())),
+            Some(_) => read_form(reader)?,
+        };
+    }
+    Ok(())
+}
+
+/// Reads the form at the next token, which must exist and not be discarded.
+fn read_form(reader: &mut Tokens) -> ParseResult {
+    let token = reader.peek().expect("should always have a value here");
+    match token.kind {
+        TokenKind::Open('(') => MalType::List(read_seq(reader, ')')?).into(),
+        TokenKind::Open('[') => MalType::Vector(read_seq(reader, ']')?).into(),
+        TokenKind::Open(_) => read_hash_map(reader),
+        TokenKind::Close(c) => Err(UnexpectedClose(c)),
+        TokenKind::Quote => quote_name(reader, "quote"),
+        TokenKind::Quasiquote => quote_name(reader, "quasiquote"),
+        TokenKind::Unquote => quote_name(reader, "unquote"),
+        TokenKind::SpliceUnquote => quote_name(reader, "splice-unquote"),
+        TokenKind::Deref => quote_name(reader, "deref"),
+        TokenKind::Meta => read_meta(reader),
+        _ => read_atom(reader),
+    }
+}
+
+fn quote_name(reader: &mut Tokens, name: &str) -> ParseResult {
+    reader.next();
+    let form = next_form(reader)?.ok_or_else(|| MissingForm(name.to_string()))?;
+    let mut v = Vector::new();
+    v.push_back(Symbol(name.into()));
+    v.push_back(form);
+    Ok(v.into())
+}
 
-    if head == "(" {
-        MalType::List(read_vector(reader, ')')?).into()
-    } else if head == "[" {
-        MalType::Vector(read_vector(reader, ']')?).into()
-    } else if head == "{" {
-        read_hash_map(reader)
-    } else if head == "'" {
-        quote_name(reader, "quote")
-    } else if head == "`" {
-        quote_name(reader, "quasiquote")
-    } else if head == "~" {
-        quote_name(reader, "unquote")
-    } else if head == "~@" {
-        quote_name(reader, "splice-unquote")
-    } else if head == "@" {
-        quote_name(reader, "deref")
-    } else {
-        read_atom(reader)
-    }
-}
-fn quote_name(reader: &mut Reader, name: &str) -> ParseResult {
+/// `^meta form` reads as `(with-meta form meta)`.
+fn read_meta(reader: &mut Tokens) -> ParseResult {
     reader.next();
+    let mut next = || next_form(reader)?.ok_or_else(|| MissingForm("with-meta".to_string()));
+    let meta = next()?;
+    let form = next()?;
     let mut v = Vector::new();
-    v.push_back(Symbol(name.to_string()));
-    v.push_back(read_form(reader)?);
+    v.push_back(Symbol("with-meta".into()));
+    v.push_back(form);
+    v.push_back(meta);
     Ok(v.into())
 }
 
-fn read_atom(reader: &mut Reader) -> ParseResult {
-    // println!("{:?} atom", reader);
-    let head = reader.next().expect("should always have a value here");
-    if head
-        .chars()
-        .next()
-        .expect("should be greater then 0 elements")
-        .is_numeric()
-        || (head.len() > 1 && (head.starts_with("-") || head.starts_with("+")))
-    {
-        match head.parse::<i64>() {
+fn read_atom(reader: &mut Tokens) -> ParseResult {
+    let token = reader.next().expect("should always have a value here");
+    let text = reader.text(token);
+    match token.kind {
+        TokenKind::Number => match text.parse::<i64>() {
             Ok(n) => Integer(n).into(),
-            Err(_) => match head.parse::<f64>() {
+            Err(_) => match text.parse::<f64>() {
                 Ok(n) => Float(n.into()).into(),
-                Err(_) => return Err(ParseError::InvalidNum(head)),
+                Err(_) => Err(ParseError::InvalidNum(text.to_string())),
             },
-        }
-    } else if head == "nil" {
-        Nil.into()
-    } else if head == "true" {
-        Bool(true).into()
-    } else if head == "false" {
-        Bool(false).into()
-    } else if head.chars().nth(0).expect("should have at least one value") == '"' {
-        if head.chars().last().unwrap() != '"' {
-            return Err(ParseError::NoClosingParen('"'));
-        }
-        Ok(MalType::String(parse_str(head)))
-    } else {
-        Symbol(head.clone()).into()
+        },
+        TokenKind::String => Ok(MalType::String(parse_str(&text[1..text.len() - 1]))),
+        TokenKind::UnterminatedString => Err(UnterminatedString),
+        TokenKind::UnterminatedComment => Err(UnterminatedComment),
+        _ => match text {
+            "nil" => Nil.into(),
+            "true" => Bool(true).into(),
+            "false" => Bool(false).into(),
+            _ => Symbol(text.into()).into(),
+        },
     }
 }
 
-fn parse_str(s: String) -> Vector<char> {
-    let mut v = Vector::new();
+/// The characters of a string literal without its quotes, escapes resolved.
+fn parse_str(s: &str) -> Vector<char> {
+    let mut v = Vec::with_capacity(s.len());
     let mut chars = s.chars();
-    chars.next().unwrap();
     while let Some(c) = chars.next() {
-        if c == '\\' {
-            match chars.next().unwrap() {
-                'n' => v.push_back('\n'),
-                next => v.push_back(next),
-            }
-        } else {
-            v.push_back(c);
+        match c {
+            '\\' => match chars.next() {
+                Some('n') => v.push('\n'),
+                Some(next) => v.push(next),
+                None => {}
+            },
+            c => v.push(c),
         }
     }
-    v.pop_back().unwrap();
-    v
+    v.into()
 }
 
-fn read_vector(reader: &mut Reader, end: char) -> Result<Vector<MalType>, ParseError> {
-    // println!("{:?} listhelper", reader);
-    reader.next().unwrap();
+/// Reads the forms of a list or vector up to its closing `end`.
+fn read_seq(reader: &mut Tokens, end: char) -> Result<Vector<MalType>, ParseError> {
+    reader.next();
     let mut result = Vector::new();
     loop {
-        if reader.peek().ok_or(NoClosingParen(end))? == end.to_string().as_str() {
-            reader.next().unwrap();
-            break;
-        } else {
-            result.push_back(read_form(reader)?)
+        skip_discarded(reader)?;
+        match reader.peek().ok_or(NoClosingParen(end))?.kind {
+            TokenKind::Close(c) if c == end => {
+                reader.next();
+                break;
+            }
+            _ => result.push_back(read_form(reader)?),
         }
     }
     Ok(result)
 }
 
-fn read_hash_map(reader: &mut Reader) -> ParseResult {
-    // println!("{:?} hashmap", reader);
-    if reader.next().expect("should always have a value here") != "{" {
-        panic!()
-    }
+fn read_hash_map(reader: &mut Tokens) -> ParseResult {
+    reader.next();
     let mut v = HashMap::new();
+    let at_end = |reader: &mut Tokens| {
+        skip_discarded(reader)?;
+        match reader.peek() {
+            Some(token) => Ok(token.kind == TokenKind::Close('}')),
+            None => Err(NoClosingParen('}')),
+        }
+    };
     loop {
-        if reader.peek().ok_or(NoClosingParen('}'))? == "}" {
+        if at_end(reader)? {
             reader.next();
             break;
         }
         let key = read_form(reader)?;
-        if reader.peek().ok_or(NoClosingParen('}'))? == "}" {
+        if at_end(reader)? {
             return Err(ParseError::MissingValue(key));
         }
         let value = read_form(reader)?;
@@ -141,22 +304,9 @@ fn read_hash_map(reader: &mut Reader) -> ParseResult {
     Ok(MalType::HashMap(v))
 }
 
-fn tokenize(text: String) -> Vec<String> {
-    let captures = REGEX.captures_iter(text.as_str());
-    let mut result: Vec<String> = vec![];
-    for capture in captures {
-        let cap =
-            capture
-                .get(1)
-                .expect("should have returned a capture")
-                .as_str()
-                .to_string();
-        if cap.starts_with(";"){
-            continue
-        } else {
-            result.push(cap);
-        }
-    }
-    // println!("{:?}", result);
-    result
+/// The tokens of `text`, comments left out.
+fn tokenize(text: &str) -> Vec<Token> {
+    Lexer::new(text)
+        .filter(|token| !matches!(token.kind, TokenKind::Comment | TokenKind::BlockComment))
+        .collect()
 }
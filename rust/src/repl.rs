@@ -0,0 +1,546 @@
+use debugger;
+use env::Env;
+use eval::{eval, load_file, safe_point, EnvBuilder, SPECIAL_FORMS};
+use gc;
+use interrupt;
+use lexer::{Lexer, Token, TokenKind};
+use reader::{read_all, Reader};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::{Hint, Hinter};
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{CompletionType, Config, Context, Editor, Helper};
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::io::{self, IsTerminal};
+use std::{env, fs};
+use types::{EvalError, MalType, ParseError, Sym};
+
+/// The most history entries kept between sessions.
+pub const HISTORY_SIZE: usize = 1000;
+
+pub struct ReplOptions {
+    /// Where history is loaded from and saved to; `None` keeps it in memory only.
+    pub history: Option<PathBuf>,
+}
+
+impl Default for ReplOptions {
+    fn default() -> Self {
+        ReplOptions {
+            history: default_history_path(),
+        }
+    }
+}
+
+/// `$XDG_DATA_HOME/mal/history`, falling back to `~/.local/share/mal/history`.
+pub fn default_history_path() -> Option<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))?;
+    Some(data_home.join("mal").join("history"))
+}
+
+/// Reads, evaluates and prints lines until EOF. Ctrl-C aborts the running form, or clears
+/// the line when nothing is running.
+pub fn run(env: Env, options: &ReplOptions) {
+    let config = Config::builder()
+        .max_history_size(HISTORY_SIZE)
+        .history_ignore_space(true)
+        .completion_type(CompletionType::List)
+        .build();
+    let mut rl = Editor::<MalHelper>::with_config(config).expect("failed to start the line editor");
+    // piped input and the test harness get one line per entry, so that unbalanced input
+    // is reported rather than waited on until EOF drops it
+    let continuation = io::stdin().is_terminal() && env::var("READLINE").map_or(true, |v| v != "false");
+    rl.set_helper(Some(MalHelper::new(env.clone(), continuation)));
+    if let Some(path) = &options.history {
+        load_history(&mut rl, path);
+    }
+    if let Err(e) = interrupt::catch_sigint() {
+        eprintln!("Ctrl-C will not interrupt running forms: {}", e);
+    }
+    let mut session = Session::new(env);
+    loop {
+        let input = match rl.readline("user> ") {
+            Ok(i) => {
+                add_history(&mut rl, &i);
+                i
+            }
+            Err(ReadlineError::Interrupted) => continue,
+            Err(_) => break,
+        };
+        interrupt::clear();
+        if input.trim_start().starts_with(':') {
+            session.command(input.trim());
+            if let Some(helper) = rl.helper_mut() {
+                helper.env = session.env.clone();
+            }
+        } else {
+            session.rep(input);
+        }
+    }
+    if let Some(path) = &options.history {
+        save_history(&mut rl, path);
+    }
+}
+
+const COMMANDS_HELP: &str = ":env            list the bindings in the environment and their types
+:load <file>    evaluate the forms in a file
+:reload         load the files loaded with :load again
+:time <expr>    evaluate and report how long it took
+:expand <form>  show a form as the reader expands it
+:type <expr>    evaluate and show the type of the result
+:reset          start over with a fresh environment
+:gc             free unreachable closures and atoms and show memory use
+:help           show this help";
+
+/// The environment a REPL evaluates in and the files loaded into it with `:load`.
+struct Session {
+    env: Env,
+    loaded: Vec<String>,
+}
+
+impl Session {
+    fn new(env: Env) -> Self {
+        for name in ["*1", "*2", "*3", "*e"] {
+            env.set(name.to_string(), MalType::Nil);
+        }
+        Session {
+            env,
+            loaded: Vec::new(),
+        }
+    }
+
+    /// Reads, evaluates and prints the forms in `input` one by one, stopping at the first
+    /// error.
+    fn rep(&self, input: String) {
+        for form in Reader::new(input.as_bytes()) {
+            let result = form.map_err(EvalError::from).and_then(|form| eval(&form, &self.env));
+            debugger::resume();
+            let failed = match result {
+                Ok(a) => {
+                    println!("{}", a.pr_str(true));
+                    remember_result(&self.env, a);
+                    false
+                }
+                Err(e) => {
+                    self.fail(e);
+                    true
+                }
+            };
+            // the result is bound to *1 by now
+            safe_point();
+            if failed {
+                break;
+            }
+        }
+    }
+
+    fn fail(&self, e: EvalError) {
+        println!("{}", e);
+        self.env.set("*e".to_string(), MalType::from(e.to_string()));
+    }
+
+    /// Handles a `:command` line.
+    fn command(&mut self, line: &str) {
+        let (command, arg) = match line.find(char::is_whitespace) {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        match command {
+            ":env" => {
+                let mut bindings = self.env.bindings();
+                bindings.sort_by(|a, b| a.0.cmp(&b.0));
+                let width = bindings.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+                for (name, value) in bindings {
+                    println!("{:width$}  {}", name, value.type_string(), width = width);
+                }
+            }
+            ":load" if !arg.is_empty() => {
+                if self.load(arg) && !self.loaded.iter().any(|f| f == arg) {
+                    self.loaded.push(arg.to_string());
+                }
+            }
+            ":reload" => {
+                if self.loaded.is_empty() {
+                    println!("No files have been loaded with :load");
+                }
+                for file in self.loaded.clone() {
+                    self.load(&file);
+                }
+            }
+            ":time" if !arg.is_empty() => {
+                let start = Instant::now();
+                self.rep(arg.to_string());
+                println!("Elapsed time: {:.3?}", start.elapsed());
+            }
+            ":expand" if !arg.is_empty() => match read_all(arg.to_string()) {
+                Ok(forms) => forms.into_iter().for_each(|f| println!("{}", f.pr_str(true))),
+                Err(e) => println!("{}", EvalError::from(e)),
+            },
+            ":type" if !arg.is_empty() => match read_all(arg.to_string()) {
+                Ok(forms) => {
+                    for form in forms {
+                        match eval(&form, &self.env) {
+                            Ok(value) => println!("{}", value.type_string()),
+                            Err(e) => {
+                                self.fail(e);
+                                break;
+                            }
+                        }
+                    }
+                }
+                Err(e) => println!("{}", EvalError::from(e)),
+            },
+            ":reset" => {
+                *self = Session::new(EnvBuilder::like(&self.env).build());
+                println!("Environment reset");
+            }
+            ":gc" => {
+                let freed = gc::collect();
+                let stats = gc::stats();
+                println!(
+                    "Freed {}; live: {} environments, {} functions, {} atoms, {} symbols",
+                    freed,
+                    stats.envs,
+                    stats.functions,
+                    stats.atoms,
+                    Sym::interned()
+                );
+            }
+            ":help" => println!("{}", COMMANDS_HELP),
+            ":load" | ":time" | ":expand" | ":type" => println!("{} needs an argument", command),
+            _ => println!("Unknown command {}, try :help", command),
+        }
+    }
+
+    fn load(&self, file: &str) -> bool {
+        match load_file(file, &self.env) {
+            Ok(value) => {
+                println!("{}", value.pr_str(true));
+                true
+            }
+            Err(e) => {
+                self.fail(e);
+                false
+            }
+        }
+    }
+}
+
+/// Shifts `value` into `*1`, moving the older results along to `*2` and `*3`.
+fn remember_result(env: &Env, value: MalType) {
+    for (to, from) in [("*3", "*2"), ("*2", "*1")] {
+        let older = env.get(from).unwrap_or(MalType::Nil);
+        env.set(to.to_string(), older);
+    }
+    env.set("*1".to_string(), value);
+}
+
+fn load_history(rl: &mut Editor<MalHelper>, path: &Path) {
+    if !path.exists() {
+        return;
+    }
+    if let Err(e) = rl.load_history(path) {
+        eprintln!("warning: could not read history from {}: {}", path.display(), e);
+    }
+}
+
+fn save_history(rl: &mut Editor<MalHelper>, path: &Path) {
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            eprintln!("warning: could not create {}: {}", dir.display(), e);
+            return;
+        }
+    }
+    if let Err(e) = rl.save_history(path) {
+        eprintln!("warning: could not save history to {}: {}", path.display(), e);
+    }
+}
+
+/// Adds `line` as the newest entry, dropping any earlier copy of it.
+fn add_history(rl: &mut Editor<MalHelper>, line: &str) {
+    if line.trim().is_empty() {
+        return;
+    }
+    let history = rl.history_mut();
+    if history.iter().any(|entry| entry == line) {
+        let kept = history
+            .iter()
+            .filter(|entry| *entry != line)
+            .cloned()
+            .collect::<Vec<String>>();
+        history.clear();
+        kept.into_iter().for_each(|entry| {
+            history.add(entry);
+        });
+    }
+    history.add(line);
+}
+
+const RESET: &str = "\x1b[0m";
+const BRACKET: &str = "\x1b[1;34m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[33m";
+const CONSTANT: &str = "\x1b[36m";
+const SPECIAL_FORM: &str = "\x1b[35m";
+const COMMENT: &str = "\x1b[90m";
+
+/// Characters that end the symbol being completed.
+const WORD_BREAKS: &[char] = &[
+    ' ', '\t', '\n', ',', '(', ')', '[', ']', '{', '}', '\'', '`', '~', '@', '"', ';',
+];
+
+/// Line editing support backed by the reader and the live environment: continuation of
+/// unbalanced input, symbol completion, highlighting and arity hints.
+pub struct MalHelper {
+    env: Env,
+    /// Whether unbalanced input continues on the next line instead of being evaluated.
+    continuation: bool,
+}
+
+impl MalHelper {
+    pub fn new(env: Env, continuation: bool) -> Self {
+        MalHelper { env, continuation }
+    }
+}
+
+impl Validator for MalHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        if !self.continuation {
+            return Ok(ValidationResult::Valid(None));
+        }
+        match read_all(ctx.input().to_string()) {
+            Err(ParseError::NoClosingParen(_))
+            | Err(ParseError::UnterminatedString)
+            | Err(ParseError::UnterminatedComment) => Ok(ValidationResult::Incomplete),
+            Err(ParseError::MissingForm(_)) if lacks_last_form(ctx.input()) => {
+                Ok(ValidationResult::Incomplete)
+            }
+            _ => Ok(ValidationResult::Valid(None)),
+        }
+    }
+}
+
+/// Whether `input` misses a form only because it ends too early, like `'` or `#_ #_ a`,
+/// so the next line can supply it. `(#_)` misses one before a closing bracket, which no
+/// more input fixes.
+fn lacks_last_form(input: &str) -> bool {
+    !matches!(read_all(format!("{}\nnil", input)), Err(ParseError::MissingForm(_)))
+}
+
+impl Completer for MalHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(WORD_BREAKS).map_or(0, |i| i + 1);
+        let prefix = &line[start..pos];
+        let mut names = self
+            .env
+            .symbols()
+            .into_iter()
+            .chain(SPECIAL_FORMS.iter().map(|s| s.to_string()))
+            .filter(|name| name.starts_with(prefix))
+            .collect::<Vec<String>>();
+        names.sort();
+        names.dedup();
+        Ok((start, names))
+    }
+}
+
+/// The parameters of the function being called, shown after the cursor.
+pub struct ArityHint(String);
+
+impl Hint for ArityHint {
+    fn display(&self) -> &str {
+        &self.0
+    }
+
+    fn completion(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl Hinter for MalHelper {
+    type Hint = ArityHint;
+
+    fn hint(&self, line: &str, pos: usize, _ctx: &Context) -> Option<ArityHint> {
+        if pos < line.len() {
+            return None;
+        }
+        let name = enclosing_call(line)?;
+        match self.env.get(&name)? {
+            MalType::NonNativeFunction(f) => {
+                let mut call = f.params.clone();
+                call.push_front(MalType::Symbol(name.into()));
+                Some(ArityHint(format!("  {}", MalType::List(call).pr_str(true))))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Highlighter for MalHelper {
+    fn highlight<'l>(&self, line: &'l str, pos: usize) -> Cow<'l, str> {
+        let brackets = matching_brackets(line, pos);
+        let mut out = String::with_capacity(line.len() * 2);
+        let mut last = 0;
+        let tokens = Lexer::new(line).collect::<Vec<Token>>();
+        for (token, discarded) in tokens.iter().zip(discarded(&tokens)) {
+            let start = token.span.start;
+            let text = token.span.text(line);
+            out.push_str(&line[last..start]);
+            let color = match brackets {
+                Some((open, close)) if start == open || start == close => Some(BRACKET),
+                _ if discarded => Some(COMMENT),
+                _ => token_color(token.kind, text),
+            };
+            match color {
+                Some(color) => {
+                    out.push_str(color);
+                    out.push_str(text);
+                    out.push_str(RESET);
+                }
+                None => out.push_str(text),
+            }
+            last = token.span.end;
+        }
+        out.push_str(&line[last..]);
+        Cow::Owned(out)
+    }
+
+    fn highlight_hint<'h>(&self, hint: &'h str) -> Cow<'h, str> {
+        Cow::Owned(format!("{}{}{}", COMMENT, hint, RESET))
+    }
+
+    fn highlight_char(&self, _line: &str, _pos: usize) -> bool {
+        true
+    }
+}
+
+impl Helper for MalHelper {}
+
+fn token_color(kind: TokenKind, text: &str) -> Option<&'static str> {
+    match kind {
+        TokenKind::String | TokenKind::UnterminatedString => Some(STRING),
+        TokenKind::Comment
+        | TokenKind::BlockComment
+        | TokenKind::UnterminatedComment
+        | TokenKind::Discard => Some(COMMENT),
+        TokenKind::Number => Some(NUMBER),
+        TokenKind::Symbol if text.starts_with(':') || text == "nil" || text == "true" || text == "false" => {
+            Some(CONSTANT)
+        }
+        TokenKind::Symbol if SPECIAL_FORMS.contains(&text) => Some(SPECIAL_FORM),
+        _ => None,
+    }
+}
+
+/// Whether each token is `#_` or part of the form one drops, as the reader sees it.
+fn discarded(tokens: &[Token]) -> Vec<bool> {
+    // the bracket depths of the `#_`s still waiting for their form
+    let mut waiting: Vec<usize> = Vec::new();
+    let mut depth = 0;
+    let mut discarded = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        let completes_form = match token.kind {
+            TokenKind::Discard => {
+                discarded.push(true);
+                waiting.push(depth);
+                continue;
+            }
+            TokenKind::Open(_) => {
+                discarded.push(!waiting.is_empty());
+                depth += 1;
+                continue;
+            }
+            TokenKind::Close(_) => {
+                discarded.push(waiting.iter().any(|&d| d < depth));
+                depth = depth.saturating_sub(1);
+                // a `#_` with no form before its list closes drops nothing
+                waiting.retain(|&d| d <= depth);
+                true
+            }
+            TokenKind::Comment | TokenKind::BlockComment => {
+                discarded.push(false);
+                false
+            }
+            // `^meta form` is one form made of two
+            TokenKind::Meta if !waiting.is_empty() => {
+                discarded.push(true);
+                waiting.push(depth);
+                false
+            }
+            TokenKind::Quote
+            | TokenKind::Quasiquote
+            | TokenKind::Unquote
+            | TokenKind::SpliceUnquote
+            | TokenKind::Deref
+            | TokenKind::Meta => {
+                discarded.push(!waiting.is_empty());
+                false
+            }
+            _ => {
+                discarded.push(!waiting.is_empty());
+                true
+            }
+        };
+        if completes_form && waiting.last() == Some(&depth) {
+            waiting.pop();
+        }
+    }
+    discarded
+}
+
+/// The offsets of the bracket at or just before `pos` and its partner.
+fn matching_brackets(line: &str, pos: usize) -> Option<(usize, usize)> {
+    let mut open = Vec::new();
+    let mut pairs = Vec::new();
+    for token in Lexer::new(line) {
+        match token.kind {
+            TokenKind::Open(_) => open.push(token.span.start),
+            TokenKind::Close(_) => {
+                if let Some(o) = open.pop() {
+                    pairs.push((o, token.span.start));
+                }
+            }
+            _ => {}
+        }
+    }
+    let at = |p: usize| pairs.iter().find(|(o, c)| *o == p || *c == p).cloned();
+    at(pos).or_else(|| pos.checked_sub(1).and_then(at))
+}
+
+/// The name at the head of the innermost unclosed list.
+fn enclosing_call(line: &str) -> Option<String> {
+    let mut heads: Vec<Option<&str>> = Vec::new();
+    let mut head_next = false;
+    let tokens = Lexer::new(line).collect::<Vec<Token>>();
+    for (token, discarded) in tokens.iter().zip(discarded(&tokens)) {
+        if discarded || matches!(token.kind, TokenKind::Comment | TokenKind::BlockComment) {
+            continue;
+        }
+        let is_head = head_next;
+        head_next = false;
+        match token.kind {
+            TokenKind::Open('(') => {
+                heads.push(None);
+                head_next = true;
+            }
+            TokenKind::Open(_) => heads.push(None),
+            TokenKind::Close(_) => {
+                heads.pop();
+            }
+            _ if is_head => {
+                if let Some(head) = heads.last_mut() {
+                    *head = Some(token.span.text(line));
+                }
+            }
+            _ => {}
+        }
+    }
+    heads.pop()?.map(str::to_string)
+}
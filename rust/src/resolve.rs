@@ -0,0 +1,155 @@
+use im_rc::Vector;
+use types::{MalType, Sym};
+
+/// The names known to be bound in one environment frame, in slot order.
+#[derive(Clone)]
+pub struct Scope {
+    names: Vec<Sym>,
+    /// Whether the frame can only gain names as they are seen bound.
+    addressable: bool,
+    /// Whether the frame's bindings are still being read, so a closure created now may
+    /// see names bound after it.
+    open: bool,
+}
+
+impl Scope {
+    fn new(names: Vec<Sym>) -> Self {
+        Scope {
+            names,
+            addressable: true,
+            open: false,
+        }
+    }
+}
+
+/// The frames around the code being analysed or compiled, innermost last, which say
+/// where each local name will be found when the code runs.
+pub struct Frames {
+    frames: Vec<Scope>,
+    /// How many of `frames` belong to the functions this code is nested in.
+    inherited: usize,
+    /// Whether the frames this code creates can be addressed by slot.
+    addressable: bool,
+}
+
+impl Frames {
+    /// The frames for code whose own frames are unknown, like a top-level form.
+    pub fn top_level(body: &MalType) -> Self {
+        Frames::new(body, Vec::new())
+    }
+
+    /// The frames for the body of a function created outside the analyser or compiler,
+    /// which knows the frames holding its name and parameters but not the ones it
+    /// closes over.
+    pub fn function(name: Option<Sym>, params: &Vector<MalType>, body: &MalType) -> Self {
+        Frames::new(body, function_frames(name, params))
+    }
+
+    /// The frames for the body of a `fn*` form found in the code these frames are for.
+    pub fn nested(&self, name: Option<Sym>, params: &Vector<MalType>, body: &MalType) -> Self {
+        let mut frames = self.frames.clone();
+        frames.extend(function_frames(name, params));
+        Frames::new(body, frames)
+    }
+
+    fn new(body: &MalType, mut frames: Vec<Scope>) -> Self {
+        let addressable = !binds_dynamically(body);
+        if !addressable {
+            frames.iter_mut().for_each(|f| f.addressable = false);
+        }
+        Frames {
+            inherited: frames.len(),
+            frames,
+            addressable,
+        }
+    }
+
+    /// The depth and slot `name` will be found at, when every frame between here and the
+    /// one binding it is known.
+    pub fn resolve(&self, name: &Sym) -> Option<(usize, usize)> {
+        for (depth, (i, frame)) in self.frames.iter().enumerate().rev().enumerate() {
+            if !frame.addressable {
+                return None;
+            }
+            if let Some(slot) = frame.names.iter().position(|n| n == name) {
+                return Some((depth, slot));
+            }
+            if frame.open && i < self.inherited {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Enters a frame, as `let*` and `loop` do, whose bindings are read next.
+    pub fn push(&mut self) {
+        self.frames.push(Scope {
+            names: Vec::new(),
+            addressable: self.addressable,
+            open: true,
+        });
+    }
+
+    /// Records that `name` is bound in the innermost frame. A name already bound there
+    /// keeps its slot.
+    pub fn bind(&mut self, name: &Sym) {
+        let frame = self.frames.last_mut().unwrap();
+        if !frame.names.contains(name) {
+            frame.names.push(name.clone());
+        }
+    }
+
+    /// Marks the bindings of the innermost frame as all read.
+    pub fn close(&mut self) {
+        self.frames.last_mut().unwrap().open = false;
+    }
+
+    /// How many frames there are.
+    pub fn count(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Leaves the frames entered since there were `count`.
+    pub fn truncate(&mut self, count: usize) {
+        self.frames.truncate(count);
+    }
+}
+
+/// The frames `InnerFunction::closure` and `Env::new_bind` put around a function body,
+/// outermost first.
+fn function_frames(name: Option<Sym>, params: &Vector<MalType>) -> Vec<Scope> {
+    let mut frames = Vec::new();
+    if let Some(name) = name {
+        frames.push(Scope::new(vec![name]));
+    }
+    let mut names = Vec::new();
+    for param in params {
+        if let MalType::Symbol(param) = param {
+            if &**param != "&" && !names.contains(param) {
+                names.push(param.clone());
+            }
+        }
+    }
+    frames.push(Scope::new(names));
+    frames
+}
+
+/// Whether running `form` can bind names in the frames around it at run time, which
+/// `def!` does and the debugger and profiler forms may do. Nested `fn*` bodies run in
+/// frames of their own and are not searched.
+fn binds_dynamically(form: &MalType) -> bool {
+    let l = match form {
+        MalType::List(l) => l,
+        MalType::Vector(v) => return v.iter().any(binds_dynamically),
+        MalType::HashMap(h) => return h.values().any(binds_dynamically),
+        _ => return false,
+    };
+    match l.head() {
+        Some(MalType::Symbol(s)) => match s.as_str() {
+            "def!" | "break!" | "profile" => true,
+            "fn*" | "quote" => false,
+            _ => l.iter().skip(1).any(binds_dynamically),
+        },
+        _ => l.iter().any(binds_dynamically),
+    }
+}
@@ -0,0 +1,187 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use types::{EvalError, MalType, MidResult};
+
+/// Limits for evaluating untrusted code, given to an environment with `EnvBuilder::sandbox`.
+/// They apply to everything evaluated in that environment and no other.
+///
+/// By default a sandbox allows every native, denies all filesystem access and has no
+/// fuel or allocation budget. The budgets are per evaluation: each call to `eval` from
+/// outside the sandbox, like each REPL form, starts with them full.
+#[derive(Clone, Debug, Default)]
+pub struct Sandbox {
+    natives: Option<HashSet<String>>,
+    paths: Vec<PathBuf>,
+    fuel: Option<u64>,
+    allocation: Option<usize>,
+}
+
+impl Sandbox {
+    pub fn new() -> Self {
+        Sandbox::default()
+    }
+
+    /// Only install the natives named here; any other native is left unbound.
+    pub fn allow_natives<S: AsRef<str>>(mut self, names: &[S]) -> Self {
+        let natives = self.natives.get_or_insert_with(HashSet::new);
+        natives.extend(names.iter().map(|n| n.as_ref().to_string()));
+        self
+    }
+
+    /// Lets `slurp` and `load-file` read files under `path`.
+    pub fn allow_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.paths.push(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// The number of eval steps allowed before `EvalError::OutOfFuel`.
+    pub fn fuel(mut self, steps: u64) -> Self {
+        self.fuel = Some(steps);
+        self
+    }
+
+    /// The number of values (collection elements and string characters) that may be
+    /// created before `EvalError::AllocationLimit`.
+    pub fn allocation_budget(mut self, values: usize) -> Self {
+        self.allocation = Some(values);
+        self
+    }
+
+    pub fn allows_native(&self, name: &str) -> bool {
+        match &self.natives {
+            Some(natives) => natives.contains(name),
+            None => true,
+        }
+    }
+}
+
+/// What is left of a sandbox's budgets. An environment built with a sandbox holds one,
+/// and evaluating in that environment spends it.
+pub struct Budget {
+    sandbox: Sandbox,
+    fuel: Cell<Option<u64>>,
+    allocation: Cell<Option<usize>>,
+}
+
+impl Budget {
+    pub fn new(sandbox: Sandbox) -> Self {
+        Budget {
+            fuel: Cell::new(sandbox.fuel),
+            allocation: Cell::new(sandbox.allocation),
+            sandbox,
+        }
+    }
+
+    pub fn sandbox(&self) -> &Sandbox {
+        &self.sandbox
+    }
+
+    /// Refills the fuel and allocation budgets.
+    pub fn refuel(&self) {
+        self.fuel.set(self.sandbox.fuel);
+        self.allocation.set(self.sandbox.allocation);
+    }
+}
+
+thread_local! {
+    /// The budget of the environment being evaluated in.
+    static ACTIVE: RefCell<Option<Rc<Budget>>> = const { RefCell::new(None) };
+}
+
+/// Keeps the sandbox of an environment active while evaluating in it, and restores the
+/// one that was active before when dropped.
+pub struct Scope {
+    previous: Option<Rc<Budget>>,
+}
+
+impl Scope {
+    /// Activates `budget`, the one of the environment about to be evaluated in. Each
+    /// evaluation that does not run inside another one in the same sandbox, like one
+    /// snippet evaluated by the host, starts with full budgets.
+    pub fn enter(budget: Option<Rc<Budget>>) -> Scope {
+        let previous = ACTIVE.with(|a| a.replace(budget.clone()));
+        if let Some(budget) = &budget {
+            let nested = previous.as_ref().is_some_and(|p| Rc::ptr_eq(p, budget));
+            if !nested {
+                budget.refuel();
+            }
+        }
+        Scope { previous }
+    }
+}
+
+impl Drop for Scope {
+    fn drop(&mut self) {
+        ACTIVE.with(|a| *a.borrow_mut() = self.previous.take());
+    }
+}
+
+/// Whether the code being evaluated is sandboxed.
+pub fn active() -> bool {
+    ACTIVE.with(|a| a.borrow().is_some())
+}
+
+fn with_active<T, F: FnOnce(&Budget) -> T>(f: F) -> Option<T> {
+    ACTIVE.with(|a| a.borrow().as_deref().map(f))
+}
+
+/// Spends one eval step.
+pub fn consume_fuel() -> MidResult<()> {
+    with_active(|budget| match budget.fuel.get() {
+        Some(0) => Err(EvalError::OutOfFuel),
+        Some(fuel) => {
+            budget.fuel.set(Some(fuel - 1));
+            Ok(())
+        }
+        None => Ok(()),
+    })
+    .unwrap_or(Ok(()))
+}
+
+/// Spends `values` units of the allocation budget.
+pub fn allocate(values: usize) -> MidResult<()> {
+    with_active(|budget| match budget.allocation.get() {
+        Some(allocation) if allocation < values => {
+            budget.allocation.set(Some(0));
+            Err(EvalError::AllocationLimit)
+        }
+        Some(allocation) => {
+            budget.allocation.set(Some(allocation - values));
+            Ok(())
+        }
+        None => Ok(()),
+    })
+    .unwrap_or(Ok(()))
+}
+
+/// Charges the allocation budget for the top level of a freshly created value.
+pub fn charge(value: &MalType) -> MidResult<()> {
+    match value {
+        MalType::List(l) | MalType::Vector(l) => allocate(l.len()),
+        MalType::String(s) => allocate(s.len()),
+        MalType::HashMap(h) => allocate(h.len() * 2),
+        _ => Ok(()),
+    }
+}
+
+/// Fails with `EvalError::AccessDenied` unless `path` is under one of the allowed paths.
+pub fn check_path(path: &str) -> MidResult<()> {
+    with_active(|budget| {
+        let denied = || EvalError::AccessDenied(path.to_string());
+        let requested = Path::new(path).canonicalize().map_err(|_| denied())?;
+        let allowed = budget
+            .sandbox
+            .paths
+            .iter()
+            .filter_map(|p| p.canonicalize().ok())
+            .any(|p| requested.starts_with(p));
+        if allowed {
+            Ok(())
+        } else {
+            Err(denied())
+        }
+    })
+    .unwrap_or(Ok(()))
+}
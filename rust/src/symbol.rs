@@ -0,0 +1,153 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
+
+/// An interned symbol name. Symbols test equality and hash by identity, so environments
+/// never hash or compare the name itself; they still order by name. A name stays interned
+/// while some symbol holds it, and symbols are per thread, like the values that hold them.
+#[derive(Clone)]
+pub struct Sym(Rc<Name>);
+
+struct Name(Box<str>);
+
+thread_local! {
+    static INTERNER: RefCell<HashMap<Box<str>, Weak<Name>>> = RefCell::new(HashMap::new());
+}
+
+impl Sym {
+    /// The symbol for `name`, interning it if no symbol holds it yet.
+    pub fn new(name: &str) -> Sym {
+        if let Some(sym) = Sym::lookup(name) {
+            return sym;
+        }
+        let sym = Sym(Rc::new(Name(name.into())));
+        INTERNER.with(|i| i.borrow_mut().insert(name.into(), Rc::downgrade(&sym.0)));
+        sym
+    }
+
+    /// The symbol for `name` if it is interned, without interning it. A name no symbol
+    /// holds cannot be bound anywhere.
+    pub fn lookup(name: &str) -> Option<Sym> {
+        INTERNER.with(|i| i.borrow().get(name).and_then(Weak::upgrade).map(Sym))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0 .0
+    }
+
+    /// How many names are interned on this thread.
+    pub fn interned() -> usize {
+        INTERNER.with(|i| i.borrow().len())
+    }
+}
+
+impl Drop for Name {
+    fn drop(&mut self) {
+        // the interner may already be gone when the thread exits
+        let _ = INTERNER.try_with(|i| i.borrow_mut().remove(&self.0));
+    }
+}
+
+impl PartialEq for Sym {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Sym {}
+
+impl Hash for Sym {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).hash(state)
+    }
+}
+
+impl Deref for Sym {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl PartialOrd for Sym {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Sym {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl From<&str> for Sym {
+    fn from(name: &str) -> Self {
+        Sym::new(name)
+    }
+}
+
+impl From<String> for Sym {
+    fn from(name: String) -> Self {
+        Sym::new(&name)
+    }
+}
+
+impl From<&String> for Sym {
+    fn from(name: &String) -> Self {
+        Sym::new(name)
+    }
+}
+
+impl From<&Sym> for Sym {
+    fn from(sym: &Sym) -> Self {
+        sym.clone()
+    }
+}
+
+/// What environments look names up by: a symbol, or a name, which is never interned just
+/// to look it up.
+pub trait SymKey {
+    fn sym(self) -> Option<Sym>;
+}
+
+impl SymKey for Sym {
+    fn sym(self) -> Option<Sym> {
+        Some(self)
+    }
+}
+
+impl SymKey for &Sym {
+    fn sym(self) -> Option<Sym> {
+        Some(self.clone())
+    }
+}
+
+impl SymKey for &str {
+    fn sym(self) -> Option<Sym> {
+        Sym::lookup(self)
+    }
+}
+
+impl SymKey for &String {
+    fn sym(self) -> Option<Sym> {
+        Sym::lookup(self)
+    }
+}
+
+impl Display for Sym {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Debug for Sym {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
@@ -1,14 +1,15 @@
 use std::cell::RefCell;
 use functions::{Functions, InnerFunction};
+use gc;
 use im_rc::{HashMap, Vector};
 use std::error;
-use std::fmt::{Debug, Display, Formatter, write};
+use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::ops::Deref;
 use std::rc::Rc;
 use types::EvalError::{InvalidType};
 
-type Sym = String;
+pub use symbol::Sym;
 
 pub type ParseResult = Result<MalType, ParseError>;
 pub type EvalResult = Result<MalType, EvalError>;
@@ -72,7 +73,7 @@ impl Atom {
 }
 
 impl Hash for Atom{
-    fn hash<H: Hasher>(&self, state: &mut H) {
+    fn hash<H: Hasher>(&self, _state: &mut H) {
         panic!("cannot hash an atom")
     }
 }
@@ -105,9 +106,9 @@ impl MalType {
         }
     }
 }
-impl Into<ParseResult> for MalType {
-    fn into(self) -> ParseResult {
-        Ok(self)
+impl From<MalType> for ParseResult {
+    fn from(val: MalType) -> Self {
+        Ok(val)
     }
 }
 
@@ -116,6 +117,14 @@ pub enum ParseError {
     NoClosingParen(char),
     InvalidNum(String),
     MissingValue(MalType),
+    /// A quote, like `'` or `@`, or a `#_` with nothing after it.
+    MissingForm(String),
+    /// A `)`, `]` or `}` that closes nothing.
+    UnexpectedClose(char),
+    UnterminatedString,
+    UnterminatedComment,
+    /// Reading the input itself failed.
+    Io(String),
 }
 
 #[derive(Debug, Clone)]
@@ -125,7 +134,16 @@ pub enum EvalError {
     WrongArgAmount,
     SymbolNotFound(String),
     ParseError(ParseError),
-    InvalidFile(String)
+    InvalidFile(String),
+    RecurNotInTail(MalType),
+    RecurOutsideLoop,
+    StackOverflow(usize),
+    OutOfFuel,
+    AllocationLimit,
+    AccessDenied(String),
+    Interrupted,
+    Timeout,
+    InvalidLogLevel(String),
 }
 
 impl Display for EvalError {
@@ -145,6 +163,33 @@ impl Display for EvalError {
             EvalError::InvalidFile(s) => {
                 write!(f, "failed at loading file: {}", s)
             }
+            EvalError::RecurNotInTail(m) => {
+                write!(f, "Can only recur from tail position: {}", m)
+            }
+            EvalError::RecurOutsideLoop => {
+                write!(f, "recur used outside of a loop")
+            }
+            EvalError::StackOverflow(depth) => {
+                write!(f, "Stack overflow: maximum eval depth of {} exceeded", depth)
+            }
+            EvalError::OutOfFuel => {
+                write!(f, "Evaluation ran out of fuel")
+            }
+            EvalError::AllocationLimit => {
+                write!(f, "Evaluation exceeded its allocation budget")
+            }
+            EvalError::AccessDenied(path) => {
+                write!(f, "Access denied: {}", path)
+            }
+            EvalError::InvalidLogLevel(level) => {
+                write!(f, "Unknown log level: {}", level)
+            }
+            EvalError::Interrupted => {
+                write!(f, "Interrupted")
+            }
+            EvalError::Timeout => {
+                write!(f, "Evaluation timed out")
+            }
         }
     }
 }
@@ -171,6 +216,17 @@ impl Display for ParseError {
             ParseError::MissingValue(m) => {
                 format!("Missing value for {}", m)
             }
+            ParseError::MissingForm(name) => {
+                format!("Missing form after {}", name)
+            }
+            ParseError::UnexpectedClose(c) => {
+                format!("Unexpected {}", c)
+            }
+            ParseError::UnterminatedString => "Unterminated string".to_string(),
+            ParseError::UnterminatedComment => "Unterminated block comment".to_string(),
+            ParseError::Io(e) => {
+                format!("could not read input: {}", e)
+            }
         };
         write!(f, "{}{}", intro_string, error_string)
     }
@@ -194,14 +250,16 @@ impl MalType {
         }
     }
     pub fn to_atom_value(self) -> Atom{
-        Atom(Rc::new(RefCell::new(self)))
+        let atom = Rc::new(RefCell::new(self));
+        gc::track_atom(&atom);
+        Atom(atom)
     }
     pub fn to_atom_mal_type(self) -> Self{
         MalType::Atom(self.to_atom_value())
     }
-    pub fn to_symbol(self) -> MidResult<String> {
+    pub fn to_symbol(&self) -> MidResult<Sym> {
         match self {
-            MalType::Symbol(s) => Ok(s),
+            MalType::Symbol(s) => Ok(s.clone()),
             _ => Err(EvalError::InvalidType(
                 "symbol".to_string(),
                 self.type_string(),
@@ -291,11 +349,7 @@ impl MalType {
         }
     }
     pub fn is_hashable(&self) -> bool {
-        match self {
-            MalType::Float(_) => false,
-            MalType::HashMap(_) => false,
-            _ => true,
-        }
+        !matches!(self, MalType::Float(_) | MalType::HashMap(_))
     }
     pub fn to_real_str(self) -> MidResult<String>{
         Ok(self.to_mal_string()?.into_iter().collect::<String>())
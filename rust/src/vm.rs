@@ -0,0 +1,188 @@
+use compiler::{compile, Chunk, Op};
+use env::Env;
+use eval;
+use eval::DepthGuard;
+use functions::InnerFunction;
+use im_rc::{HashMap, Vector};
+use interrupt;
+use sandbox;
+use std::mem;
+use std::rc::Rc;
+use types::EvalError::{InvalidHashKey, InvalidType, SymbolNotFound, WrongArgAmount};
+use types::{EvalResult, MalType};
+
+/// A caller waiting for the function it called to return.
+struct Frame {
+    chunk: Rc<Chunk>,
+    ip: usize,
+    env: Env,
+    scopes: Vec<Env>,
+    base: usize,
+    /// Counts the callee against the eval depth limit until it returns.
+    _depth: DepthGuard,
+}
+
+/// Compiles `ast` and runs it in `env`.
+pub fn eval(ast: &MalType, env: &Env) -> EvalResult {
+    run(Rc::new(compile(ast)?), env.clone())
+}
+
+/// Runs the body of `f` in `env`, where its parameters are already bound.
+pub fn call(f: &InnerFunction, env: Env) -> EvalResult {
+    run(f.code(), env)
+}
+
+fn run(mut chunk: Rc<Chunk>, mut env: Env) -> EvalResult {
+    let _depth = DepthGuard::enter()?;
+    let mut ip = 0;
+    // the environments `PushScope` left, innermost last
+    let mut scopes: Vec<Env> = Vec::new();
+    // where the running function's values start on the stack
+    let mut base = 0;
+    let mut stack: Vec<MalType> = Vec::new();
+    let mut frames: Vec<Frame> = Vec::new();
+    loop {
+        let op = &chunk.ops[ip];
+        ip += 1;
+        match op {
+            Op::Const(value) => stack.push(value.clone()),
+            Op::Get(name) => stack.push(env.get(name).ok_or_else(|| SymbolNotFound(name.to_string()))?),
+            Op::GetLocal { depth, slot, name } => {
+                let value = match env.get_slot(*depth, *slot, name) {
+                    Some(value) => value,
+                    None => env.get(name).ok_or_else(|| SymbolNotFound(name.to_string()))?,
+                };
+                stack.push(value);
+            }
+            Op::Def(name) => env.set(name, stack.last().unwrap().clone()),
+            Op::Pop => {
+                stack.pop();
+            }
+            Op::Jump(target) => ip = *target,
+            Op::JumpIfFalse(target) => {
+                if !stack.pop().unwrap().to_bool() {
+                    ip = *target;
+                }
+            }
+            Op::PushScope => {
+                let inner = env.new_env();
+                scopes.push(mem::replace(&mut env, inner));
+            }
+            Op::Bind(name) => env.set(name, stack.pop().unwrap()),
+            Op::PopScope => env = scopes.pop().unwrap(),
+            Op::Closure(proto) => {
+                let closure = InnerFunction::closure(
+                    proto.name.clone(),
+                    proto.params.clone(),
+                    proto.body.clone(),
+                    &env,
+                );
+                if let MalType::NonNativeFunction(f) = &closure {
+                    let _ = f.code.set(proto.chunk.clone());
+                }
+                stack.push(closure);
+            }
+            Op::Call(argc, head) | Op::TailCall(argc, head) => {
+                interrupt::check()?;
+                let tail = matches!(op, Op::TailCall(..));
+                let args = stack
+                    .drain(stack.len() - argc..)
+                    .collect::<Vector<MalType>>();
+                let f = stack.pop().unwrap();
+                // the tree walker reports calls to the tracer, debugger and profiler
+                if eval::hooked() {
+                    let head = head.as_ref().map(|h| h.as_str());
+                    stack.push(eval::call_hooked(head, f, args)?);
+                    continue;
+                }
+                match f {
+                    MalType::Function(f) => stack.push(f.call(args)?),
+                    MalType::NonNativeFunction(f) if !eval::is_current(&f.env) => {
+                        stack.push(eval::apply(MalType::NonNativeFunction(f), args)?)
+                    }
+                    MalType::NonNativeFunction(f) => {
+                        let callee_env = f.env.new_bind(&f.params, args)?;
+                        if tail {
+                            stack.truncate(base);
+                            scopes.clear();
+                            env = callee_env;
+                            chunk = f.code();
+                        } else {
+                            frames.push(Frame {
+                                chunk: mem::replace(&mut chunk, f.code()),
+                                ip,
+                                env: mem::replace(&mut env, callee_env),
+                                scopes: mem::take(&mut scopes),
+                                base,
+                                _depth: DepthGuard::enter()?,
+                            });
+                            base = stack.len();
+                        }
+                        ip = 0;
+                    }
+                    other => return Err(InvalidType("Function".to_string(), other.type_string())),
+                }
+            }
+            Op::Recur {
+                argc,
+                start,
+                scope,
+                params,
+            } => {
+                interrupt::check()?;
+                if *argc != params.len() {
+                    return Err(WrongArgAmount);
+                }
+                let args = stack
+                    .drain(stack.len() - argc..)
+                    .collect::<Vector<MalType>>();
+                scopes.truncate(scope + 1);
+                env = scopes[*scope].new_bind(params, args)?;
+                ip = *start;
+            }
+            Op::Fuel => sandbox::consume_fuel()?,
+            Op::Allocate(values) => sandbox::allocate(*values)?,
+            Op::MakeVector(len) => {
+                let items = stack
+                    .drain(stack.len() - len..)
+                    .collect::<Vector<MalType>>();
+                stack.push(MalType::Vector(items));
+            }
+            Op::MakeMap(len) => {
+                let mut map = HashMap::new();
+                let mut items = stack.drain(stack.len() - 2 * len..);
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    map.insert(key, value);
+                }
+                drop(items);
+                stack.push(MalType::HashMap(map));
+            }
+            Op::CheckKey => {
+                let key = stack.last().unwrap();
+                if !key.is_hashable() {
+                    return Err(InvalidHashKey(key.clone()));
+                }
+            }
+            Op::Tree(form) => {
+                let value = eval::walk_form(form, &env)?;
+                stack.push(value);
+            }
+            Op::Fail(e) => return Err(e.clone()),
+            Op::Return => {
+                let value = stack.pop().unwrap();
+                stack.truncate(base);
+                match frames.pop() {
+                    None => return Ok(value),
+                    Some(caller) => {
+                        chunk = caller.chunk;
+                        ip = caller.ip;
+                        env = caller.env;
+                        scopes = caller.scopes;
+                        base = caller.base;
+                        stack.push(value);
+                    }
+                }
+            }
+        }
+    }
+}
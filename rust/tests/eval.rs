@@ -0,0 +1,50 @@
+extern crate mal_rust;
+
+use mal_rust::env::Env;
+use mal_rust::eval::{rep, repl_env, EnvBuilder};
+use mal_rust::types::EvalError;
+use std::thread;
+
+/// Recurses `n` levels deep, outside tail position.
+const F: &str = "(def! f (fn* (n) (if (= n 0) 0 (+ 1 (f (- n 1))))))";
+
+fn run(text: &str, env: &Env) -> Result<String, EvalError> {
+    rep(text.to_string(), env)
+}
+
+#[test]
+fn deep_recursion_fails_before_a_main_thread_stack_runs_out() {
+    // the stack of a main thread, where the default limit has to fit
+    let overflowed = thread::Builder::new()
+        .stack_size(8 * 1024 * 1024)
+        .spawn(|| {
+            let env = repl_env();
+            run(F, &env).unwrap();
+            matches!(run("(f 5000)", &env), Err(EvalError::StackOverflow(_)))
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+    assert!(overflowed);
+}
+
+#[test]
+fn each_environment_keeps_its_own_depth_limit() {
+    let shallow = EnvBuilder::new().max_depth(50).build();
+    run(F, &shallow).unwrap();
+    let other = repl_env();
+    run(F, &other).unwrap();
+    assert!(matches!(run("(f 100)", &shallow), Err(EvalError::StackOverflow(50))));
+    assert_eq!(run("(f 100)", &other).unwrap(), "100");
+    // a function from the shallow environment keeps its limit when called from another
+    other.set("g", shallow.get("f").unwrap());
+    assert!(matches!(run("(g 100)", &other), Err(EvalError::StackOverflow(_))));
+}
+
+#[test]
+fn rebuilt_environments_keep_their_settings() {
+    let shallow = EnvBuilder::new().max_depth(50).build();
+    let fresh = EnvBuilder::like(&shallow).build();
+    run(F, &fresh).unwrap();
+    assert!(matches!(run("(f 100)", &fresh), Err(EvalError::StackOverflow(50))));
+}
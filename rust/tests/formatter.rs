@@ -0,0 +1,23 @@
+extern crate mal_rust;
+
+use mal_rust::formatter::format;
+
+#[test]
+fn formatting_keeps_comments_discards_and_reader_macros() {
+    let source = "; about f\n(def! f (fn* [x] ; the body\n  '(a ~x @y #_ gone ^{:m 1} [z])))\n\n\n(f 1)";
+    let formatted = format(source, 80).unwrap();
+    assert_eq!(
+        formatted,
+        "; about f\n(def! f\n  (fn* [x] ; the body\n    '(a ~x @y #_gone ^{:m 1} [z])))\n\n(f 1)"
+    );
+    assert_eq!(format(&formatted, 80).unwrap(), formatted);
+}
+
+#[test]
+fn deeply_nested_forms_that_fit_stay_on_their_line() {
+    let depth = 200;
+    let vectors = format!("{}x{}", "[".repeat(depth), "]".repeat(depth));
+    assert_eq!(format(&vectors, 80).unwrap(), vectors);
+    let quotes = format!("{}x", "'".repeat(depth));
+    assert_eq!(format(&quotes, 80).unwrap(), quotes);
+}
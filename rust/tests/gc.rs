@@ -0,0 +1,77 @@
+extern crate im_rc;
+extern crate mal_rust;
+
+use im_rc::Vector;
+use mal_rust::env::Env;
+use mal_rust::eval::{apply, eval, rep, repl_env, safe_point};
+use mal_rust::gc;
+use mal_rust::reader::read_str;
+use mal_rust::types::MalType;
+
+/// `(mk)` returns a list long enough to keep its items in shared storage, holding a closure
+/// over the frame that binds the list.
+const MK: &str = "(def! mk (fn* [] (let* [n 42 code (list (fn* [& _] n) \
+                  1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 \
+                  21 22 23 24 25 26 27 28 29 30)] code)))";
+
+fn run(text: &str, env: &Env) -> String {
+    rep(text.to_string(), env).unwrap()
+}
+
+#[test]
+fn collecting_keeps_closures_only_rust_holds() {
+    let env = repl_env();
+    run(MK, &env);
+    let code = eval(&read_str("(mk)".to_string()).unwrap(), &env).unwrap();
+    gc::collect();
+    let f = match &code {
+        MalType::List(items) => items[0].clone(),
+        other => panic!("expected a list, got {}", other),
+    };
+    assert_eq!(apply(f, Vector::new()).unwrap(), MalType::Integer(42));
+}
+
+#[test]
+fn gc_in_the_middle_of_a_form_keeps_what_it_uses() {
+    let env = repl_env();
+    run(MK, &env);
+    // evaluating the list calls the closure in it
+    run("(def! use (fn* [code _] (eval code)))", &env);
+    assert_eq!(run("(use (mk) (gc))", &env), "42");
+    assert_eq!(run("(let* [code (mk)] (do (gc) (eval code)))", &env), "42");
+    safe_point();
+    assert_eq!(run("(eval (mk))", &env), "42");
+}
+
+#[test]
+fn collecting_frees_cycles_nothing_uses() {
+    let env = repl_env();
+    gc::collect();
+    let before = gc::stats();
+    run("(let* [a (atom nil)] (do (reset! a a) nil))", &env);
+    run("(let* [x 1] (do (def! f (fn* [] f)) nil))", &env);
+    run("((fn* g [n] (if (> n 0) (g (- n 1)) n)) 3)", &env);
+    assert!(gc::collect() > 0);
+    let after = gc::stats();
+    assert_eq!(after.atoms, before.atoms);
+    assert_eq!(after.functions, before.functions);
+    assert_eq!(after.envs, before.envs);
+}
+
+#[test]
+fn collecting_keeps_closures_held_in_shared_storage() {
+    let env = repl_env();
+    // each frame holds the vector holding the closures of every frame after it
+    run("(def! mk (fn* [n acc] (if (= n 0) acc (mk (- n 1) [(fn* [] n) acc]))))", &env);
+    run("(do (def! chain (mk 1000 nil)) nil)", &env);
+    gc::collect();
+    let before = gc::stats();
+    assert_eq!(gc::collect(), 0);
+    assert!(before.functions > 1000);
+    assert_eq!(gc::stats().functions, before.functions);
+    let f = match env.get("chain") {
+        Some(MalType::Vector(items)) => items[0].clone(),
+        other => panic!("expected a vector, got {:?}", other),
+    };
+    assert_eq!(apply(f, Vector::new()).unwrap(), MalType::Integer(1));
+}
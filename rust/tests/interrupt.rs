@@ -0,0 +1,27 @@
+extern crate mal_rust;
+
+use mal_rust::eval::{eval_with_timeout, rep, repl_env};
+use mal_rust::interrupt;
+use mal_rust::reader::read_str;
+use mal_rust::types::EvalError;
+use std::time::Duration;
+
+const SPIN: &str = "(loop [i 0] (recur (+ i 1)))";
+
+#[test]
+fn catching_sigint_again_keeps_the_handler() {
+    interrupt::catch_sigint().unwrap();
+    interrupt::catch_sigint().unwrap();
+}
+
+#[test]
+fn interrupts_and_deadlines_stop_running_forms() {
+    let env = repl_env();
+    interrupt::interrupt();
+    assert!(matches!(rep(SPIN.to_string(), &env), Err(EvalError::Interrupted)));
+    let spin = read_str(SPIN.to_string()).unwrap();
+    let result = eval_with_timeout(&spin, &env, Duration::from_millis(50));
+    assert!(matches!(result, Err(EvalError::Timeout)));
+    // the deadline is gone once the form has stopped
+    assert_eq!(rep("(+ 1 2)".to_string(), &env).unwrap(), "3");
+}
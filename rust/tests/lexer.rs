@@ -0,0 +1,78 @@
+extern crate mal_rust;
+
+use mal_rust::lexer::TokenKind::*;
+use mal_rust::lexer::{tokenize, TokenKind};
+
+/// Each token's kind and text.
+fn tokens(text: &str) -> Vec<(TokenKind, &str)> {
+    tokenize(text).into_iter().map(|t| (t.kind, t.span.text(text))).collect()
+}
+
+#[test]
+fn tokens_have_kinds_and_spans() {
+    let text = "(def! x [1 -2 +3.5 -y]) ; done";
+    let spans: Vec<(usize, usize)> = tokenize(text).iter().map(|t| (t.span.start, t.span.end)).collect();
+    assert_eq!(
+        spans,
+        [(0, 1), (1, 5), (6, 7), (8, 9), (9, 10), (11, 13), (14, 18), (19, 21), (21, 22), (22, 23), (24, 30)]
+    );
+    assert_eq!(
+        tokens(text),
+        [
+            (Open('('), "("),
+            (Symbol, "def!"),
+            (Symbol, "x"),
+            (Open('['), "["),
+            (Number, "1"),
+            (Number, "-2"),
+            (Number, "+3.5"),
+            (Symbol, "-y"),
+            (Close(']'), "]"),
+            (Close(')'), ")"),
+            (Comment, "; done"),
+        ]
+    );
+}
+
+#[test]
+fn reader_macros_strings_and_comments_are_single_tokens() {
+    assert_eq!(
+        tokens("'a `b ~c ~@d @e ^{} #_f"),
+        [
+            (Quote, "'"),
+            (Symbol, "a"),
+            (Quasiquote, "`"),
+            (Symbol, "b"),
+            (Unquote, "~"),
+            (Symbol, "c"),
+            (SpliceUnquote, "~@"),
+            (Symbol, "d"),
+            (Deref, "@"),
+            (Symbol, "e"),
+            (Meta, "^"),
+            (Open('{'), "{"),
+            (Close('}'), "}"),
+            (Discard, "#_"),
+            (Symbol, "f"),
+        ]
+    );
+    assert_eq!(
+        tokens(r#""a \"b\" (c)" #| x #| y |# z |# w"#),
+        [(String, r#""a \"b\" (c)""#), (BlockComment, "#| x #| y |# z |#"), (Symbol, "w")]
+    );
+    assert_eq!(tokens("a;b\nc"), [(Symbol, "a"), (Comment, ";b"), (Symbol, "c")]);
+}
+
+#[test]
+fn spans_count_bytes_and_skip_whitespace_and_commas() {
+    let text = "é,\u{3000}✓\t\"ü\"";
+    let spans: Vec<(usize, usize)> = tokenize(text).iter().map(|t| (t.span.start, t.span.end)).collect();
+    assert_eq!(spans, [(0, 2), (6, 9), (10, 14)]);
+    assert_eq!(tokens(text), [(Symbol, "é"), (Symbol, "✓"), (String, "\"ü\"")]);
+}
+
+#[test]
+fn unterminated_strings_and_comments_run_to_the_end() {
+    assert_eq!(tokens(r#"a "b \" c"#), [(Symbol, "a"), (UnterminatedString, r#""b \" c"#)]);
+    assert_eq!(tokens("a #| b #| c |#"), [(Symbol, "a"), (UnterminatedComment, "#| b #| c |#")]);
+}
@@ -0,0 +1,67 @@
+extern crate mal_rust;
+
+use mal_rust::eval::{rep, Engine, EnvBuilder};
+use mal_rust::types::EvalError;
+
+const ENGINES: [Engine; 2] = [Engine::Tree, Engine::Vm];
+
+fn run(engine: Engine, text: &str) -> Result<String, EvalError> {
+    let env = EnvBuilder::new().engine(engine).build();
+    rep(text.to_string(), &env)
+}
+
+#[test]
+fn recur_in_tail_position_loops_on_both_engines() {
+    let programs = [
+        ("(loop [i 0 acc 0] (if (< i 10) (recur (+ i 1) (+ acc i)) acc))", "45"),
+        ("(loop (i 0) (do (+ i 1) (if (= i 3) i (recur (+ i 1)))))", "3"),
+        ("(loop [i 0] (let* [j (+ i 1)] (if (> j 4) i (recur j))))", "4"),
+        ("(loop [i 0] (if (< i 20000) (recur (+ i 1)) i))", "20000"),
+        // the inner recur goes back to the inner loop
+        (
+            "(loop [i 0 acc 0] (if (< i 3) (recur (+ i 1) (loop [j 0 a acc] (if (< j 2) (recur (+ j 1) (+ a 1)) a))) acc))",
+            "6",
+        ),
+        ("(loop [f (fn* [x] (loop [y x] (if (< y 5) (recur (+ y 1)) y)))] (f 1))", "5"),
+    ];
+    for engine in ENGINES {
+        for (program, expected) in programs {
+            assert_eq!(run(engine, program).unwrap(), expected, "{:?}: {}", engine, program);
+        }
+    }
+}
+
+#[test]
+fn recur_outside_tail_position_fails_before_the_loop_runs() {
+    let programs = [
+        "(loop [i 0] (+ 1 (recur i)))",
+        "(loop [i 0] (do (recur 1) 2))",
+        "(loop [i 0] (if (recur 1) 1 2))",
+        "(loop [i 0] [(recur 1)])",
+        "(loop [i 0] {:k (recur 1)})",
+        "(loop [i 0] (let* [j (recur 1)] j))",
+    ];
+    for engine in ENGINES {
+        for program in programs {
+            let result = run(engine, program);
+            assert!(matches!(result, Err(EvalError::RecurNotInTail(_))), "{:?}: {}: {:?}", engine, program, result);
+        }
+        // nothing in the body runs before the check
+        let env = EnvBuilder::new().engine(engine).build();
+        let result = rep("(loop [i 0] (do (def! ran true) (+ 1 (recur i))))".to_string(), &env);
+        assert!(matches!(result, Err(EvalError::RecurNotInTail(_))));
+        assert!(matches!(rep("ran".to_string(), &env), Err(EvalError::SymbolNotFound(_))));
+    }
+}
+
+#[test]
+fn recur_without_a_loop_or_with_the_wrong_arguments_fails() {
+    for engine in ENGINES {
+        for program in ["(recur 1)", "(if true (recur 1) 2)", "(loop [i 0] (fn* [] (recur 1)))"] {
+            let result = run(engine, program);
+            assert!(matches!(result, Err(EvalError::RecurOutsideLoop)), "{:?}: {}: {:?}", engine, program, result);
+        }
+        let result = run(engine, "(loop [i 0] (if (= i 0) (recur 1 2) i))");
+        assert!(matches!(result, Err(EvalError::WrongArgAmount)), "{:?}: {:?}", engine, result);
+    }
+}
@@ -0,0 +1,18 @@
+extern crate mal_rust;
+
+use mal_rust::eval::{rep, repl_env};
+
+#[test]
+fn atoms_that_hold_themselves_print_once() {
+    let env = repl_env();
+    let run = |text: &str| rep(text.to_string(), &env).unwrap();
+    assert_eq!(
+        run("(do (def! a (atom nil)) (reset! a a))"),
+        "(atom (atom ...))"
+    );
+    assert_eq!(run("(reset! a [1 a])"), "[1 (atom [1 (atom ...)])]");
+    assert_eq!(
+        run("(do (def! b (atom a)) (pr-str b b))"),
+        "\"(atom (atom [1 (atom ...)])) (atom (atom [1 (atom ...)]))\""
+    );
+}
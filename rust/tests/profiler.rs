@@ -0,0 +1,21 @@
+extern crate mal_rust;
+
+use mal_rust::eval::{rep, repl_env};
+use mal_rust::profiler;
+
+#[test]
+fn folded_stacks_come_depth_first_with_callees_by_name() {
+    let env = repl_env();
+    rep("(def! f (fn* (n) (if (= n 0) 0 (+ 1 (f (- n 1))))))".to_string(), &env).unwrap();
+    let (result, profile) = profiler::profile(|| rep("(f 2)".to_string(), &env));
+    assert_eq!(result.unwrap(), "2");
+    assert_eq!(profile.functions["f"].calls, 3);
+    let mut folded = Vec::new();
+    profile.write_folded(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    let stacks: Vec<&str> = folded.lines().map(|line| line.rsplit_once(' ').unwrap().0).collect();
+    assert_eq!(
+        stacks,
+        ["f", "f;+", "f;-", "f;=", "f;f", "f;f;+", "f;f;-", "f;f;=", "f;f;f", "f;f;f;="]
+    );
+}
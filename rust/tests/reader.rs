@@ -0,0 +1,98 @@
+extern crate mal_rust;
+
+use mal_rust::reader::{read_all, Reader};
+use mal_rust::types::{MalType, ParseError};
+use std::io::{self, Read};
+
+/// A source that hands out its input in the given pieces, one per read.
+struct Pieces<'a>(Vec<&'a [u8]>);
+
+impl<'a> Read for Pieces<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.0.is_empty() {
+            return Ok(0);
+        }
+        let piece = self.0.remove(0);
+        let read = piece.len().min(buf.len());
+        buf[..read].copy_from_slice(&piece[..read]);
+        if read < piece.len() {
+            self.0.insert(0, &piece[read..]);
+        }
+        Ok(read)
+    }
+}
+
+fn stream(pieces: Vec<&[u8]>) -> Vec<Result<MalType, ParseError>> {
+    Reader::new(Pieces(pieces)).collect()
+}
+
+fn forms(pieces: Vec<&[u8]>) -> Vec<MalType> {
+    stream(pieces).into_iter().map(Result::unwrap).collect()
+}
+
+fn read(text: &str) -> Result<Vec<MalType>, ParseError> {
+    read_all(text.to_string()).map(|forms| forms.into_iter().collect())
+}
+
+#[test]
+fn streams_read_the_same_forms_whatever_size_the_reads() {
+    let text = "(def! s \"héllo wörld ✓\") ; a comment\n symbol123 -42 3.5 [1 {:k \"v\"}] 'x #| a |# \"✓\"";
+    let expected: Vec<MalType> = read_all(text.to_string()).unwrap().into_iter().collect();
+    assert_eq!(expected.len(), 7);
+    for size in [1, 2, 3, 5, 8, 13] {
+        assert_eq!(forms(text.as_bytes().chunks(size).collect()), expected, "reads of {} bytes", size);
+    }
+}
+
+#[test]
+fn characters_split_between_reads_are_joined() {
+    let check = "✓".as_bytes();
+    let read = forms(vec![b"(\"a", &check[..1], &check[1..2], &check[2..], b"\" \xc3", b"\xa9)"]);
+    assert_eq!(read, forms(vec!["(\"a✓\" é)".as_bytes()]));
+    assert!(matches!(stream(vec![b"(a \xc3"]).as_slice(), [Err(ParseError::Io(_))]));
+}
+
+#[test]
+fn tokens_split_between_reads_are_joined() {
+    assert_eq!(forms(vec![b"abc", b"def 12", b"34 \"x", b" y\""]), forms(vec![b"abcdef 1234 \"x y\""]));
+    assert_eq!(forms(vec![b"~", b"@x"]), forms(vec![b"~@x"]));
+    assert_eq!(forms(vec![b"#", b"| a |", b"# b"]), forms(vec![b"b"]));
+}
+
+#[test]
+fn discards_at_the_end_of_a_read_drop_the_form_in_the_next() {
+    assert_eq!(forms(vec![b"(a) #_", b"(b) c"]), forms(vec![b"(a) c"]));
+    assert_eq!(forms(vec![b"(a) #", b"_ b c"]), forms(vec![b"(a) c"]));
+    let read = stream(vec![b"(a) #_"]);
+    assert!(matches!(read.as_slice(), [Ok(_), Err(ParseError::MissingForm(_))]));
+}
+
+#[test]
+fn discards_and_block_comments_read_as_nothing() {
+    assert_eq!(read("(a #_ b c)").unwrap(), read("(a c)").unwrap());
+    assert_eq!(read("[#_ #_ a b c] {#_ :x :k #_ [1] :v}").unwrap(), read("[c] {:k :v}").unwrap());
+    assert_eq!(read("'#_ a b #_ c").unwrap(), read("'b").unwrap());
+    assert_eq!(read("(a #| b #| (c |# d |# e)").unwrap(), read("(a e)").unwrap());
+}
+
+#[test]
+fn unterminated_strings_and_comments_fail_to_read() {
+    assert!(matches!(read(r#"(a "b)"#), Err(ParseError::UnterminatedString)));
+    assert!(matches!(read(r#""a \""#), Err(ParseError::UnterminatedString)));
+    assert!(matches!(read("a #| b #| c |#"), Err(ParseError::UnterminatedComment)));
+    assert!(matches!(read("(a #| b)"), Err(ParseError::UnterminatedComment)));
+    assert!(matches!(read("(a b"), Err(ParseError::NoClosingParen(')'))));
+}
+
+#[test]
+fn stray_closing_brackets_and_missing_forms_fail_to_read() {
+    assert!(matches!(read("a)"), Err(ParseError::UnexpectedClose(')'))));
+    assert!(matches!(read("(a]"), Err(ParseError::UnexpectedClose(']'))));
+    assert!(matches!(read("[a }]"), Err(ParseError::UnexpectedClose('}'))));
+    assert!(matches!(read("(#_)"), Err(ParseError::MissingForm(_))));
+    assert!(matches!(read("a #_"), Err(ParseError::MissingForm(_))));
+    assert!(matches!(read("'"), Err(ParseError::MissingForm(_))));
+    // a stream returns the forms before the error, then stops
+    let read = stream(vec![b"1 2) 3"]);
+    assert!(matches!(read.as_slice(), [Ok(_), Ok(_), Err(ParseError::UnexpectedClose(')'))]));
+}
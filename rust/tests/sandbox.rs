@@ -0,0 +1,120 @@
+extern crate mal_rust;
+
+use mal_rust::eval::{rep, Engine, EnvBuilder};
+use mal_rust::sandbox::Sandbox;
+use mal_rust::types::EvalError;
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+const ENGINES: [Engine; 2] = [Engine::Tree, Engine::Vm];
+
+fn run(engine: Engine, sandbox: Sandbox, text: &str) -> Result<String, EvalError> {
+    let env = EnvBuilder::new().engine(engine).sandbox(sandbox).build();
+    rep(text.to_string(), &env)
+}
+
+/// The least fuel `text` runs with on `engine`.
+fn fuel_needed(engine: Engine, text: &str) -> u64 {
+    let runs = |fuel| match run(engine, Sandbox::new().fuel(fuel), text) {
+        Err(EvalError::OutOfFuel) => false,
+        Ok(_) => true,
+        Err(e) => panic!("{}: {}", text, e),
+    };
+    let (mut low, mut high) = (0, 10_000);
+    assert!(runs(high), "{} needs more than {} fuel", text, high);
+    while low < high {
+        let mid = (low + high) / 2;
+        if runs(mid) {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    low
+}
+
+#[test]
+fn both_engines_spend_fuel_alike() {
+    let programs = [
+        "(loop [i 0] (if (< i 100) (recur (+ i 1)) i))",
+        "((fn* f [n] (if (= n 0) 0 (+ 1 (f (- n 1))))) 5)",
+        "(let* [x 1 y [x {:a x}]] (do (def! z y) (quote (1 2)) z))",
+        "(do)",
+        "(if false 1)",
+        "(not (= 1 2))",
+        "((fn* [& xs] xs) 1 2 3)",
+        "(let* [a (atom 0)] (swap! a (fn* [x] (+ x 1))))",
+    ];
+    for program in programs {
+        assert_eq!(fuel_needed(Engine::Tree, program), fuel_needed(Engine::Vm, program), "{}", program);
+    }
+}
+
+#[test]
+fn loops_run_out_of_fuel_on_both_engines() {
+    let program = "(loop [i 0] (if (< i 100) (recur (+ i 1)) i))";
+    for engine in ENGINES {
+        assert!(matches!(run(engine, Sandbox::new().fuel(1000), program), Err(EvalError::OutOfFuel)));
+        assert_eq!(run(engine, Sandbox::new().fuel(10_000), program).unwrap(), "100");
+    }
+}
+
+#[test]
+fn allocations_past_the_budget_fail_on_both_engines() {
+    let programs = [
+        "[1 2 3 4 5 6]",
+        "(list 1 2 3 4 5 6)",
+        "{:a 1 :b 2 :c 3}",
+        "(let* [x [1 2 3]] [x x x])",
+        "((fn* [& xs] xs) 1 2 3 4 5 6)",
+    ];
+    for engine in ENGINES {
+        for program in programs {
+            let result = run(engine, Sandbox::new().allocation_budget(5), program);
+            assert!(matches!(result, Err(EvalError::AllocationLimit)), "{:?}: {}: {:?}", engine, program, result);
+            assert!(run(engine, Sandbox::new().allocation_budget(100), program).is_ok(), "{:?}: {}", engine, program);
+        }
+    }
+}
+
+#[test]
+fn budgets_refill_for_each_evaluation() {
+    let program = "(loop [i 0] (if (< i 5) (recur (+ i 1)) i))";
+    for engine in ENGINES {
+        let fuel = fuel_needed(engine, program);
+        let fueled = EnvBuilder::new().engine(engine).sandbox(Sandbox::new().fuel(fuel)).build();
+        let allocating = EnvBuilder::new().engine(engine).sandbox(Sandbox::new().allocation_budget(5)).build();
+        for _ in 0..3 {
+            assert_eq!(rep(program.to_string(), &fueled).unwrap(), "5");
+            assert_eq!(rep("[1 2 3]".to_string(), &allocating).unwrap(), "[1 2 3]");
+        }
+    }
+}
+
+#[test]
+fn files_outside_the_allowed_paths_are_denied_on_both_engines() {
+    let dir = env::temp_dir().join(format!("mal-sandbox-{}", process::id()));
+    let allowed = dir.join("allowed");
+    fs::create_dir_all(&allowed).unwrap();
+    fs::write(allowed.join("in.mal"), "(+ 1 2)").unwrap();
+    fs::write(dir.join("out.mal"), "(+ 3 4)").unwrap();
+    let path = |p: PathBuf| p.to_str().unwrap().to_string();
+    let inside = path(allowed.join("in.mal"));
+    let escaping = path(allowed.join("..").join("out.mal"));
+    let missing = path(allowed.join("missing.mal"));
+    for engine in ENGINES {
+        let sandbox = || Sandbox::new().allow_path(&allowed);
+        assert_eq!(run(engine, sandbox(), &format!("(slurp {:?})", inside)).unwrap(), "\"(+ 1 2)\"");
+        assert_eq!(run(engine, sandbox(), &format!("(load-file {:?})", inside)).unwrap(), "nil");
+        for file in [&escaping, &missing] {
+            for program in [format!("(slurp {:?})", file), format!("(load-file {:?})", file)] {
+                let result = run(engine, sandbox(), &program);
+                assert!(matches!(result, Err(EvalError::AccessDenied(_))), "{:?}: {}: {:?}", engine, program, result);
+            }
+        }
+        // without an allowed path nothing can be read
+        let result = run(engine, Sandbox::new(), &format!("(slurp {:?})", inside));
+        assert!(matches!(result, Err(EvalError::AccessDenied(_))), "{:?}: {:?}", engine, result);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}